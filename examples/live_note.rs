@@ -9,7 +9,7 @@ use torque_tracker_engine::{
     manager::{AudioManager, OutputConfig, ToWorkerMsg},
     project::{
         event_command::NoteCommand,
-        note_event::{Note, NoteEvent, VolumeEffect},
+        note_event::{Note, NoteAction, NoteEvent, VolumeEffect},
         song::{Song, SongOperation},
     },
     sample::{Sample, SampleMetaData},
@@ -35,6 +35,8 @@ fn main() {
         vibrato_rate: 0,
         vibrato_waveform: VibratoWave::default(),
         base_note: Note::new(64).unwrap(),
+        sample_loop: None,
+        sustain_loop: None,
     };
 
     manager
@@ -65,7 +67,7 @@ fn main() {
         .unwrap();
 
     let note_event = NoteEvent {
-        note: Note::new(70).unwrap(),
+        note: NoteAction::Play(Note::new(70).unwrap()),
         sample_instr: 1,
         vol: VolumeEffect::None,
         command: NoteCommand::None,
@@ -9,7 +9,7 @@ use torque_tracker_engine::{
     manager::{AudioManager, OutputConfig, PlaybackSettings, ToWorkerMsg},
     project::{
         event_command::NoteCommand,
-        note_event::{Note, NoteEvent, VolumeEffect},
+        note_event::{Note, NoteAction, NoteEvent, VolumeEffect},
         pattern::{InPatternPosition, PatternOperation},
         song::{Song, SongOperation},
     },
@@ -36,6 +36,8 @@ fn main() {
         vibrato_depth: 0,
         vibrato_rate: 0,
         vibrato_waveform: VibratoWave::default(),
+        sample_loop: None,
+        sustain_loop: None,
     };
 
     let mut song = manager.try_edit_song().unwrap();
@@ -48,7 +50,7 @@ fn main() {
                 channel: i as u8,
             },
             event: NoteEvent {
-                note: Note::new(60 + (i as u8) * 2).unwrap(),
+                note: NoteAction::Play(Note::new(60 + (i as u8) * 2).unwrap()),
                 sample_instr: 0,
                 vol: VolumeEffect::None,
                 command: NoteCommand::None,
@@ -4,7 +4,7 @@ use torque_tracker_engine::{
     manager::PlaybackSettings,
     project::{
         event_command::NoteCommand,
-        note_event::{Note, NoteEvent, VolumeEffect},
+        note_event::{Note, NoteAction, NoteEvent, VolumeEffect},
         pattern::InPatternPosition,
         song::Song,
     },
@@ -32,7 +32,7 @@ fn main() {
     song.patterns[0].set_event(
         InPatternPosition { row: 0, channel: 0 },
         NoteEvent {
-            note: Note::default(),
+            note: NoteAction::Play(Note::default()),
             sample_instr: 0,
             vol: VolumeEffect::None,
             command: NoteCommand::None,
@@ -41,7 +41,7 @@ fn main() {
     song.patterns[0].set_event(
         InPatternPosition { row: 0, channel: 2 },
         NoteEvent {
-            note: Note::default(),
+            note: NoteAction::Play(Note::default()),
             sample_instr: 0,
             vol: VolumeEffect::None,
             command: NoteCommand::None,
@@ -0,0 +1,19 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+extern crate impulse_engine;
+
+fuzz_target!(|data: &[u8]| {
+    // the first byte selects the format, the rest is the compressed data
+    let Some((&format, mut data)) = data.split_first() else {
+        return;
+    };
+    let len = data.len() * 4;
+    let _ = impulse_engine::file::impulse_format::compression::decompress(
+        &mut data,
+        len,
+        format & 1 != 0,
+        format & 2 != 0,
+        &mut |_| (),
+    );
+});
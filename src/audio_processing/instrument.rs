@@ -1 +1,178 @@
+use std::num::NonZero;
 
+use crate::project::instrument::{Envelope, EnvelopeLoop, Instrument};
+
+use super::Frame;
+
+/// position of a voice in one envelope of its instrument. Advances by one tick per tick
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct EnvelopePosition {
+    tick: u16,
+}
+
+impl EnvelopePosition {
+    pub fn tick(self) -> u16 {
+        self.tick
+    }
+
+    /// Interpolated between the nodes around the position. None if the envelope is disabled
+    pub fn value(self, envelope: &Envelope) -> Option<f32> {
+        if !envelope.enabled {
+            return None;
+        }
+        let next = envelope.nodes.iter().position(|node| node.tick > self.tick);
+        match next {
+            // before the first node
+            Some(0) => envelope.nodes.first().map(|node| f32::from(node.value)),
+            Some(idx) => {
+                let (from, to) = (envelope.nodes[idx - 1], envelope.nodes[idx]);
+                // saturating, as the nodes of a broken file might not be sorted
+                let progress = f32::from(self.tick.saturating_sub(from.tick))
+                    / f32::from(to.tick.saturating_sub(from.tick).max(1));
+                let (from, to) = (f32::from(from.value), f32::from(to.value));
+                Some(from + (to - from) * progress.min(1.))
+            }
+            // the last value is held
+            None => envelope.nodes.last().map(|node| f32::from(node.value)),
+        }
+    }
+
+    /// the sustain loop is left once the note is released
+    fn active_loop(envelope: &Envelope, released: bool) -> Option<EnvelopeLoop> {
+        envelope
+            .sustain_loop
+            .filter(|_| !released)
+            .or(envelope.envelope_loop)
+    }
+
+    /// Moves one tick forward. Jumps back to the start of the active loop after its end
+    pub fn advance(&mut self, envelope: &Envelope, released: bool) {
+        let node_tick = |idx: u8| envelope.nodes.get(usize::from(idx)).map(|node| node.tick);
+        self.tick = self.tick.saturating_add(1);
+        if let Some(env_loop) = Self::active_loop(envelope, released) {
+            if let (Some(start), Some(end)) = (node_tick(env_loop.start), node_tick(env_loop.end)) {
+                if self.tick > end {
+                    self.tick = start;
+                }
+                return;
+            }
+        }
+        // stays on the last node
+        if let Some(last) = envelope.nodes.last() {
+            self.tick = self.tick.min(last.tick);
+        }
+    }
+
+    /// the last node was reached and no loop is active
+    pub fn is_done(self, envelope: &Envelope, released: bool) -> bool {
+        Self::active_loop(envelope, released).is_none()
+            && envelope
+                .nodes
+                .last()
+                .is_none_or(|last| self.tick >= last.tick)
+    }
+}
+
+/// positions of a voice in the envelopes of its instrument
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct VoiceEnvelopes {
+    pub volume: EnvelopePosition,
+    pub pan: EnvelopePosition,
+    pub pitch: EnvelopePosition,
+}
+
+impl VoiceEnvelopes {
+    /// continues the envelopes with the carry flag from the positions of the previous voice
+    pub fn carry(&mut self, previous: Self, instrument: &Instrument) {
+        if instrument.volume_envelope.carry {
+            self.volume = previous.volume;
+        }
+        if instrument.pan_envelope.carry {
+            self.pan = previous.pan;
+        }
+        if instrument.pitch_envelope.carry {
+            self.pitch = previous.pitch;
+        }
+    }
+}
+
+/// IT resonant low pass filter. look at player/filters.c of schism tracker
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Filter {
+    input_gain: f32,
+    feedback: [f32; 2],
+    /// the last two outputs
+    history: [Frame; 2],
+}
+
+impl Filter {
+    pub const MAX_CUTOFF: u8 = 127;
+
+    /// Without a filter envelope the modifier is 256
+    pub const NO_MODIFIER: f32 = 256.;
+
+    /// cutoff and resonance range 0..=127. The modifier of the filter envelope ranges -256..=256
+    pub fn set(&mut self, cutoff: u8, resonance: u8, modifier: f32, out_rate: NonZero<u32>) {
+        let out_rate = out_rate.get() as f32;
+        let frequency =
+            110. * (0.25 + f32::from(cutoff) * (modifier + 256.) / const { 24. * 512. }).exp2();
+        // frequencies above nyquist make the filter unstable
+        let frequency = frequency.clamp(120., 20_000.).min(out_rate / 2.);
+        let fc = frequency * std::f32::consts::TAU / out_rate;
+        let damping = 10f32.powf(-(24. / 128.) * f32::from(resonance) / 20.);
+        let d = ((1. - 2. * damping) * fc).min(2.);
+        let d = (2. * damping - d) / fc;
+        let e = 1. / (fc * fc);
+        self.input_gain = 1. / (1. + d + e);
+        self.feedback = [(d + e + e) / (1. + d + e), -e / (1. + d + e)];
+    }
+
+    pub fn process(&mut self, input: Frame) -> Frame {
+        let out = input * self.input_gain
+            + self.history[0] * self.feedback[0]
+            + self.history[1] * self.feedback[1];
+        self.history = [out, self.history[0]];
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::EnvelopePosition;
+    use crate::project::instrument::{Envelope, EnvelopeLoop, EnvelopeNode};
+
+    fn envelope() -> Envelope {
+        Envelope {
+            enabled: true,
+            nodes: vec![
+                EnvelopeNode { tick: 0, value: 0 },
+                EnvelopeNode { tick: 4, value: 64 },
+                EnvelopeNode { tick: 8, value: 32 },
+            ],
+            envelope_loop: None,
+            sustain_loop: Some(EnvelopeLoop { start: 1, end: 2 }),
+            carry: false,
+        }
+    }
+
+    #[test]
+    fn interpolates_and_loops_until_release() {
+        let envelope = envelope();
+        let mut position = EnvelopePosition::default();
+        let mut values = Vec::new();
+        for _ in 0..12 {
+            values.push(position.value(&envelope).unwrap());
+            position.advance(&envelope, false);
+        }
+        assert_eq!(values[..5], [0., 16., 32., 48., 64.]);
+        // the sustain loop goes from tick 4 to 8 and back
+        assert_eq!(values[8..], [32., 64., 56., 48.]);
+        assert!(!position.is_done(&envelope, false));
+
+        for _ in 0..10 {
+            position.advance(&envelope, true);
+        }
+        assert_eq!(position.tick(), 8);
+        assert!(position.is_done(&envelope, true));
+    }
+}
@@ -3,10 +3,34 @@ use std::{array, ops::IndexMut};
 
 use dasp::sample::ToSample;
 
+use crate::file::impulse_format::sample::VibratoWave;
+
 pub(crate) mod instrument;
 pub mod playback;
 pub(crate) mod sample;
 
+/// xorshift32. Good enough for random waveforms
+pub(crate) fn next_random(state: &mut u32) -> u32 {
+    *state ^= *state << 13;
+    *state ^= *state >> 17;
+    *state ^= *state << 5;
+    *state
+}
+
+/// Used by vibrato, tremolo and sample vibrato.
+/// One period of the waveform is 256 positions long. Output ranges -64..=64
+pub(crate) fn waveform(wave: VibratoWave, position: u8, random: &mut u32) -> f32 {
+    match wave {
+        VibratoWave::Sine => {
+            (f32::from(position) * const { std::f32::consts::TAU / 256. }).sin() * 64.
+        }
+        VibratoWave::RampDown => 64. - f32::from(position) / 2.,
+        VibratoWave::Square if position < 128 => 64.,
+        VibratoWave::Square => -64.,
+        VibratoWave::Random => (next_random(random) % 129) as f32 - 64.,
+    }
+}
+
 #[repr(transparent)]
 #[derive(Clone, Copy, Default, Debug, PartialEq)]
 pub struct Frame([f32; 2]);
@@ -126,6 +150,12 @@ impl Frame {
         self.0[1] *= angle.sin();
     }
 
+    /// centered, with the right side phase inverted
+    pub fn pan_surround(&mut self) {
+        self.pan_constant_power(std::f32::consts::FRAC_PI_4);
+        self.0[1] = -self.0[1];
+    }
+
     // /// angle in radians between 0 and 90°
     // pub fn pan_compromise(&mut self, angle: f32) {
     //     self.0[0] *= f32::sqrt(
@@ -0,0 +1,103 @@
+use crate::{audio_processing::sample::SamplePlayer, channel::Pan, project::song::Song};
+
+/// a voice that was pushed out of its channel by a new note. Keeps playing until it ends
+#[derive(Debug)]
+pub(super) struct BackgroundVoice {
+    pub channel: u8,
+    pub voice: SamplePlayer,
+    /// volume of the channel when the voice was pushed out
+    pub volume: u8,
+    /// pan of the channel when the voice was pushed out
+    pub pan: Pan,
+}
+
+impl BackgroundVoice {
+    /// rough loudness, used to decide which voice is stolen
+    fn loudness(&self) -> f32 {
+        self.voice.volume_factor() * f32::from(self.volume)
+    }
+}
+
+/// Voices of past notes, played by the New Note Action of their instrument.
+/// Preallocated on the heap, as they are too large to be moved around with the playback
+#[derive(Debug)]
+pub(super) struct BackgroundVoices(Box<[Option<BackgroundVoice>]>);
+
+impl Default for BackgroundVoices {
+    fn default() -> Self {
+        Self((0..Self::MAX).map(|_| None).collect())
+    }
+}
+
+impl BackgroundVoices {
+    /// same as IT: up to 256 voices, including the voice of each channel
+    pub const MAX: usize = 256 - Song::MAX_CHANNELS;
+
+    /// When all voices are used the quietest one is stolen. The pushed voice is always kept
+    pub fn push(&mut self, voice: BackgroundVoice) {
+        let slot = match self.0.iter().position(Option::is_none) {
+            Some(slot) => slot,
+            None => self
+                .0
+                .iter()
+                .enumerate()
+                .flat_map(|(idx, voice)| voice.as_ref().map(|voice| (idx, voice.loudness())))
+                .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                .map(|(idx, _)| idx)
+                .unwrap(),
+        };
+        self.0[slot] = Some(voice);
+    }
+
+    pub fn slots(&mut self) -> impl Iterator<Item = &mut Option<BackgroundVoice>> {
+        self.0.iter_mut()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &BackgroundVoice> {
+        self.0.iter().flatten()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut BackgroundVoice> {
+        self.0.iter_mut().flatten()
+    }
+
+    /// the background voices of a channel
+    pub fn channel(&mut self, channel: u8) -> impl Iterator<Item = &mut SamplePlayer> {
+        self.iter_mut()
+            .filter(move |voice| voice.channel == channel)
+            .map(|voice| &mut voice.voice)
+    }
+
+    /// removes the background voices of the channel for which keep returns false
+    pub fn retain_channel<F: FnMut(&mut SamplePlayer) -> bool>(
+        &mut self,
+        channel: u8,
+        mut keep: F,
+    ) {
+        for slot in &mut self.0 {
+            if slot
+                .as_mut()
+                .is_some_and(|voice| voice.channel == channel && !keep(&mut voice.voice))
+            {
+                *slot = None;
+            }
+        }
+    }
+
+    pub fn cut_channel(&mut self, channel: u8) {
+        self.retain_channel(channel, |_| false);
+    }
+
+    /// moves the voices out and leaves no slots behind. Doesn't allocate
+    pub fn take(&mut self) -> Self {
+        Self(std::mem::take(&mut self.0))
+    }
+
+    pub fn clear(&mut self) {
+        self.0.fill_with(|| None);
+    }
+
+    pub fn count(&self) -> usize {
+        self.0.iter().flatten().count()
+    }
+}
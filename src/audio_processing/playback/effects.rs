@@ -0,0 +1,366 @@
+use crate::{
+    audio_processing::{sample::SamplePlayer, waveform},
+    channel::Pan,
+    file::impulse_format::sample::VibratoWave,
+    project::{
+        event_command::NoteCommand,
+        note_event::{NoteEvent, VolumeEffect},
+    },
+};
+
+use super::{Oscillator, PlaybackIter, PlaybackState};
+
+impl Oscillator {
+    /// 0 in either half of the param keeps the old value
+    pub(super) fn set(&mut self, param: u8, depth_factor: u8) {
+        let (speed, depth) = (param >> 4, param & 0xF);
+        if speed != 0 {
+            self.speed = speed;
+        }
+        if depth != 0 {
+            self.depth = depth * depth_factor;
+        }
+    }
+
+    pub(super) fn set_wave(&mut self, value: u8) {
+        // can't fail, only the lower two bits are used
+        self.wave = VibratoWave::try_from(value & 0b11).unwrap();
+        self.keep_position = (value & 0b100) != 0;
+    }
+
+    /// called when a new note is played
+    pub(super) fn retrigger(&mut self) {
+        if !self.keep_position {
+            self.position = 0;
+        }
+    }
+
+    /// value of the waveform at the current position, then moves the position forward.
+    /// Vibrato and tremolo move four times faster than panbrello.
+    fn next(&mut self, random: &mut u32, speed_factor: u8) -> f32 {
+        let value = waveform(self.wave, self.position, random);
+        self.position = self.position.wrapping_add(self.speed * speed_factor);
+        value
+    }
+}
+
+/// positive amounts raise the pitch.
+/// With linear slides the amount is in 1/64 semitones, otherwise in amiga period units.
+fn slide_pitch(voice: &mut SamplePlayer, amount: f32, linear: bool) {
+    if linear {
+        voice.slide_pitch(amount);
+    } else {
+        voice.slide_period(-amount);
+    }
+}
+
+/// slides the pitch offset towards zero, which is the pitch of the note the voice was retargeted to
+fn slide_to_note(voice: &mut SamplePlayer, speed: u8, linear: bool) {
+    let offset = voice.pitch_offset();
+    if offset == 0. {
+        return;
+    }
+    let amount = f32::from(speed) * 4.;
+    slide_pitch(voice, if offset > 0. { -amount } else { amount }, linear);
+    // stop at the note instead of sliding past it
+    if voice.pitch_offset().signum() != offset.signum() {
+        voice.set_pitch_offset(0.);
+    }
+}
+
+/// Dxx, Nxy and Wxy all use this format. Returns the amount the volume changes on this tick.
+fn volume_slide_amount(param: u8, first_tick: bool) -> Option<i8> {
+    match (param >> 4, param & 0xF) {
+        // fine slides. DxF has priority, so DFF slides up
+        (x, 0xF) if x != 0 => first_tick.then_some(x as i8),
+        (0xF, y) if y != 0 => first_tick.then_some(-(y as i8)),
+        (x, 0) => (!first_tick).then_some(x as i8),
+        (0, y) => (!first_tick).then_some(-(y as i8)),
+        // invalid combination, ignored like in IT
+        _ => None,
+    }
+}
+
+/// Dxx. Also the volume slide part of Kxx and Lxx
+fn volume_slide(voice: &mut SamplePlayer, param: u8, first_tick: bool) {
+    if let Some(amount) = volume_slide_amount(param, first_tick) {
+        voice.slide_volume(amount);
+    }
+}
+
+impl<const INTERPOLATION: u8> PlaybackIter<'_, '_, INTERPOLATION> {
+    /// speeds of the volume column Gx. Same as the ones used by IT
+    const VOL_COL_PORTA_SPEED: [u8; 10] = [0, 1, 4, 8, 16, 32, 64, 96, 128, 255];
+    /// the parameters of the volume column effects range 0..=9. Larger values can't come from a file,
+    /// but can be written through the API, so they are clamped
+    const MAX_VOL_COL_VALUE: u8 = 9;
+
+    /// Set volume and set pan of the volume column. Applied together with the note of the row,
+    /// so a delayed note gets them as well
+    pub(super) fn volume_column_set(&mut self, channel: u8, effect: VolumeEffect) {
+        let channel = usize::from(channel);
+        match effect {
+            VolumeEffect::Volume(volume) => {
+                if let Some(voice) = &mut self.state.voices[channel] {
+                    voice.set_volume(volume);
+                }
+            }
+            VolumeEffect::Panning(pan) => self.state.channels[channel].set_pan(Pan::Value(pan)),
+            _ => (),
+        }
+    }
+
+    /// Called on every tick, after the note of the row was triggered.
+    /// Set volume and set pan are applied by volume_column_set
+    pub(super) fn volume_effect(&mut self, channel: u8, effect: VolumeEffect) {
+        let first_tick = self.state.tick == 0;
+        let linear = self.song.linear_slides;
+        let channel = usize::from(channel);
+        let state = &mut self.state.channels[channel];
+        let voice = &mut self.state.voices[channel];
+
+        match effect {
+            // volume slides A-D share one memory, that isn't used by any other effect
+            VolumeEffect::FineVolSlideUp(x) if first_tick => {
+                let amount = state.memory.vol_col_slide(x.min(Self::MAX_VOL_COL_VALUE));
+                if let Some(voice) = voice {
+                    voice.slide_volume(amount as i8);
+                }
+            }
+            VolumeEffect::FineVolSlideDown(x) if first_tick => {
+                let amount = state.memory.vol_col_slide(x.min(Self::MAX_VOL_COL_VALUE));
+                if let Some(voice) = voice {
+                    voice.slide_volume(-(amount as i8));
+                }
+            }
+            VolumeEffect::VolSlideUp(x) => {
+                let amount = state.memory.vol_col_slide(x.min(Self::MAX_VOL_COL_VALUE));
+                if let Some(voice) = voice.as_mut().filter(|_| !first_tick) {
+                    voice.slide_volume(amount as i8);
+                }
+            }
+            VolumeEffect::VolSlideDown(x) => {
+                let amount = state.memory.vol_col_slide(x.min(Self::MAX_VOL_COL_VALUE));
+                if let Some(voice) = voice.as_mut().filter(|_| !first_tick) {
+                    voice.slide_volume(-(amount as i8));
+                }
+            }
+            // Ex and Fx behave like Exx and Fxx with four times the value
+            VolumeEffect::PitchSlideDown(x) => {
+                let amount = state.memory.pitch_slide(x.min(Self::MAX_VOL_COL_VALUE) * 4);
+                if let Some(voice) = voice.as_mut().filter(|_| !first_tick) {
+                    slide_pitch(voice, -f32::from(amount) * 4., linear);
+                }
+            }
+            VolumeEffect::PitchSlideUp(x) => {
+                let amount = state.memory.pitch_slide(x.min(Self::MAX_VOL_COL_VALUE) * 4);
+                if let Some(voice) = voice.as_mut().filter(|_| !first_tick) {
+                    slide_pitch(voice, f32::from(amount) * 4., linear);
+                }
+            }
+            VolumeEffect::SlideToNoteWithSpeed(x) => {
+                let speed = state.memory.porta(
+                    Self::VOL_COL_PORTA_SPEED[usize::from(x.min(Self::MAX_VOL_COL_VALUE))],
+                    self.song.compatible_gxx,
+                );
+                if let Some(voice) = voice.as_mut().filter(|_| !first_tick) {
+                    slide_to_note(voice, speed, linear);
+                }
+            }
+            VolumeEffect::VibratoWithSpeed(x) => {
+                // sets the depth and keeps the speed of the last vibrato
+                state.vibrato.set(x.min(Self::MAX_VOL_COL_VALUE), 4);
+                self.vibrato(channel);
+            }
+            _ => (),
+        }
+    }
+
+    /// Exx and Fxx. EFx and FFx are fine slides, EEx and FEx extra fine slides.
+    /// Those are only applied on the first tick, normal slides on all other ticks.
+    pub(super) fn pitch_slide_command(&mut self, channel: u8, value: u8, up: bool) {
+        let first_tick = self.state.tick == 0;
+        let channel = usize::from(channel);
+        let param = self.state.channels[channel].memory.pitch_slide(value);
+        let amount = match (param >> 4, param & 0xF) {
+            (0xF, x) if first_tick => f32::from(x) * 4.,
+            (0xE, x) if first_tick => f32::from(x),
+            (0xE | 0xF, _) => return,
+            _ if !first_tick => f32::from(param) * 4.,
+            _ => return,
+        };
+        if let Some(voice) = &mut self.state.voices[channel] {
+            let amount = if up { amount } else { -amount };
+            slide_pitch(voice, amount, self.song.linear_slides);
+        }
+    }
+
+    /// Gxx. Also the first part of Lxx, which is G00
+    pub(super) fn porta_command(&mut self, channel: u8, value: u8) {
+        let channel = usize::from(channel);
+        let speed = self.state.channels[channel]
+            .memory
+            .porta(value, self.song.compatible_gxx);
+        if let Some(voice) = self.state.voices[channel]
+            .as_mut()
+            .filter(|_| self.state.tick != 0)
+        {
+            slide_to_note(voice, speed, self.song.linear_slides);
+        }
+    }
+
+    /// Dxx. Also the second part of Kxx and Lxx
+    pub(super) fn volume_slide_command(&mut self, channel: u8, value: u8) {
+        let channel = usize::from(channel);
+        let param = self.state.channels[channel].memory.volume_slide(value);
+        if let Some(voice) = &mut self.state.voices[channel] {
+            volume_slide(voice, param, self.state.tick == 0);
+        }
+    }
+
+    /// Hxy, Uxy, Kxx and volume column H. The parameters need to be set before.
+    pub(super) fn vibrato(&mut self, channel: usize) {
+        // IT updates vibrato on every tick. Old effects skip the first tick and are twice as deep
+        let (update, depth_divisor) = if self.song.old_effects {
+            (self.state.tick != 0, 16.)
+        } else {
+            (true, 32.)
+        };
+        if !update {
+            return;
+        }
+        let vibrato = &mut self.state.channels[channel].vibrato;
+        let value = vibrato.next(&mut self.state.random, 4);
+        if let Some(voice) = &mut self.state.voices[channel] {
+            voice.add_pitch_modulation(value * f32::from(vibrato.depth) / depth_divisor);
+        }
+    }
+
+    /// Rxy. The parameters need to be set before.
+    pub(super) fn tremolo(&mut self, channel: usize) {
+        let tremolo = &mut self.state.channels[channel].tremolo;
+        let value = tremolo.next(&mut self.state.random, 4);
+        if let Some(voice) = &mut self.state.voices[channel] {
+            // depth 0xF moves the volume by up to 30
+            voice.add_volume_modulation((value * f32::from(tremolo.depth) / 128.) as i8);
+        }
+    }
+
+    /// Ixy. On for x ticks, then off for y ticks
+    pub(super) fn tremor_command(&mut self, channel: u8, value: u8) {
+        let channel = usize::from(channel);
+        let param = self.state.channels[channel].memory.tremor(value);
+        let (on, off) = (param >> 4, param & 0xF);
+        // IT treats 0 as 1, old effects add one to both
+        let (on, off) = if self.song.old_effects {
+            (on + 1, off + 1)
+        } else {
+            (on.max(1), off.max(1))
+        };
+        if let Some(voice) = &mut self.state.voices[channel] {
+            voice.tremor(on, off);
+        }
+    }
+
+    /// Jxy. Cycles between the note, the note + x and the note + y semitones
+    pub(super) fn arpeggio_command(&mut self, channel: u8, value: u8) {
+        let channel = usize::from(channel);
+        let param = self.state.channels[channel].memory.arpeggio(value);
+        let semitones = match self.state.tick % 3 {
+            0 => 0,
+            1 => param >> 4,
+            _ => param & 0xF,
+        };
+        if let Some(voice) = &mut self.state.voices[channel] {
+            voice.add_pitch_modulation(f32::from(semitones) * 64.);
+        }
+    }
+
+    /// Qxy. Restarts the sample every y ticks and changes the volume according to x.
+    /// The tick on which the note of the row was played isn't counted
+    pub(super) fn retrigger_command(&mut self, channel: u8, value: u8, note_played: bool) {
+        let channel = usize::from(channel);
+        let param = self.state.channels[channel].memory.retrigger(value);
+        let Some(voice) = &mut self.state.voices[channel] else {
+            return;
+        };
+        if note_played || !voice.count_retrigger((param & 0xF).max(1)) {
+            return;
+        }
+        voice.restart();
+        let volume = voice.volume();
+        let volume = match param >> 4 {
+            0x1..=0x5 => volume.saturating_sub(1 << ((param >> 4) - 1)),
+            0x6 => volume * 2 / 3,
+            0x7 => volume / 2,
+            0x9..=0xD => volume + (1 << ((param >> 4) - 9)),
+            0xE => volume * 3 / 2,
+            0xF => volume * 2,
+            _ => volume,
+        };
+        // clamps to the max volume
+        voice.set_volume(volume);
+    }
+
+    /// Nxy. Same format as Dxx
+    pub(super) fn channel_volume_slide_command(&mut self, channel: u8, value: u8) {
+        let state = &mut self.state.channels[usize::from(channel)];
+        let param = state.memory.channel_volume_slide(value);
+        if let Some(amount) = volume_slide_amount(param, self.state.tick == 0) {
+            state.volume = state
+                .volume
+                .saturating_add_signed(amount)
+                .min(PlaybackState::MAX_CHANNEL_VOLUME);
+        }
+    }
+
+    /// Wxy. Same format as Dxx. The memory is per channel
+    pub(super) fn global_volume_slide_command(&mut self, channel: u8, value: u8) {
+        let state = &mut self.state.channels[usize::from(channel)];
+        let param = state.memory.global_volume_slide(value);
+        if let Some(amount) = volume_slide_amount(param, self.state.tick == 0) {
+            self.state.global_volume = self
+                .state
+                .global_volume
+                .saturating_add_signed(amount)
+                .min(PlaybackState::MAX_GLOBAL_VOLUME);
+        }
+    }
+
+    /// Pxy. Px0 slides left and P0x right, PxF and PFx are fine slides.
+    pub(super) fn pan_slide_command(&mut self, channel: u8, value: u8) {
+        let state = &mut self.state.channels[usize::from(channel)];
+        let param = state.memory.pan_slide(value);
+        // surround and disabled channels don't slide
+        if let (Some(amount), Pan::Value(pan)) =
+            (volume_slide_amount(param, self.state.tick == 0), state.pan)
+        {
+            state.pan = Pan::Value(pan.saturating_add_signed(-amount).min(64));
+        }
+    }
+
+    /// Yxy. The parameters need to be set before.
+    pub(super) fn panbrello(&mut self, channel: usize) {
+        let state = &mut self.state.channels[channel];
+        let value = state.panbrello.next(&mut self.state.random, 1);
+        // depth 0xF moves the pan by up to 30
+        state.pan_modulation = (value * f32::from(state.panbrello.depth) / 32.) as i8;
+    }
+
+    /// Oxx. The start position of a new note in frames. xx is combined with the high offset set by SAy.
+    /// The memory was already updated on the first tick of the row
+    pub(super) fn sample_offset(&mut self, channel: u8, event: &NoteEvent, len: usize) -> usize {
+        if !matches!(event.command, NoteCommand::SetSampleOffset(_)) {
+            return 0;
+        }
+        let state = &mut self.state.channels[usize::from(channel)];
+        let low = state.memory.sample_offset(0);
+        let offset = (usize::from(state.high_offset) << 16) | (usize::from(low) << 8);
+        match offset {
+            offset if offset < len => offset,
+            // old effects play from the end, which ends the note. IT ignores the offset
+            _ if self.song.old_effects => len,
+            _ => 0,
+        }
+    }
+}
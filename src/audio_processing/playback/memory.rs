@@ -0,0 +1,144 @@
+/// Last parameters of the commands of a channel. A parameter of 0 reuses the stored one.
+/// Which commands share a slot follows schism tracker.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(super) struct EffectMemory {
+    /// volume column A-D. Not shared with Dxx
+    vol_col_slide: u8,
+    /// Dxx, Kxx and Lxx
+    volume_slide: u8,
+    /// Exx, Fxx and volume column E and F.
+    /// Also Gxx and volume column G when compatible Gxx is off
+    pitch_slide: u8,
+    /// Gxx, Lxx and volume column G when compatible Gxx is on
+    porta: u8,
+    tremor: u8,
+    arpeggio: u8,
+    channel_volume_slide: u8,
+    sample_offset: u8,
+    pan_slide: u8,
+    retrigger: u8,
+    special: u8,
+    tempo: u8,
+    global_volume_slide: u8,
+}
+
+/// a value of 0 means that the last value is used again
+fn use_memory(memory: &mut u8, value: u8) -> u8 {
+    if value != 0 {
+        *memory = value;
+    }
+    *memory
+}
+
+impl EffectMemory {
+    pub(super) fn vol_col_slide(&mut self, value: u8) -> u8 {
+        use_memory(&mut self.vol_col_slide, value)
+    }
+
+    pub(super) fn volume_slide(&mut self, value: u8) -> u8 {
+        use_memory(&mut self.volume_slide, value)
+    }
+
+    /// volume column E and F pass four times their value
+    pub(super) fn pitch_slide(&mut self, value: u8) -> u8 {
+        use_memory(&mut self.pitch_slide, value)
+    }
+
+    /// Without compatible Gxx the portamento shares its memory with Exx and Fxx, like in IT.
+    /// Volume column G passes its speed from the table
+    pub(super) fn porta(&mut self, value: u8, compatible_gxx: bool) -> u8 {
+        if compatible_gxx {
+            use_memory(&mut self.porta, value)
+        } else {
+            use_memory(&mut self.pitch_slide, value)
+        }
+    }
+
+    pub(super) fn tremor(&mut self, value: u8) -> u8 {
+        use_memory(&mut self.tremor, value)
+    }
+
+    pub(super) fn arpeggio(&mut self, value: u8) -> u8 {
+        use_memory(&mut self.arpeggio, value)
+    }
+
+    pub(super) fn channel_volume_slide(&mut self, value: u8) -> u8 {
+        use_memory(&mut self.channel_volume_slide, value)
+    }
+
+    /// only the low part of the offset. The high part is set with SAy
+    pub(super) fn sample_offset(&mut self, value: u8) -> u8 {
+        use_memory(&mut self.sample_offset, value)
+    }
+
+    pub(super) fn pan_slide(&mut self, value: u8) -> u8 {
+        use_memory(&mut self.pan_slide, value)
+    }
+
+    pub(super) fn retrigger(&mut self, value: u8) -> u8 {
+        use_memory(&mut self.retrigger, value)
+    }
+
+    pub(super) fn special(&mut self, value: u8) -> u8 {
+        use_memory(&mut self.special, value)
+    }
+
+    pub(super) fn tempo(&mut self, value: u8) -> u8 {
+        use_memory(&mut self.tempo, value)
+    }
+
+    /// the memory is per channel, even though the global volume isn't
+    pub(super) fn global_volume_slide(&mut self, value: u8) -> u8 {
+        use_memory(&mut self.global_volume_slide, value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::EffectMemory;
+
+    #[test]
+    fn zero_reuses_last_value() {
+        let mut memory = EffectMemory::default();
+        assert_eq!(memory.volume_slide(0), 0);
+        assert_eq!(memory.volume_slide(0x12), 0x12);
+        assert_eq!(memory.volume_slide(0), 0x12);
+        assert_eq!(memory.volume_slide(0x03), 0x03);
+        assert_eq!(memory.volume_slide(0), 0x03);
+    }
+
+    #[test]
+    fn pitch_slides_share_with_porta_without_compatible_gxx() {
+        let mut memory = EffectMemory::default();
+        // E10, then G00 and F00
+        memory.pitch_slide(0x10);
+        assert_eq!(memory.porta(0, false), 0x10);
+        assert_eq!(memory.pitch_slide(0), 0x10);
+        // G20, then E00
+        memory.porta(0x20, false);
+        assert_eq!(memory.pitch_slide(0), 0x20);
+        // volume column F2 is the same as F08
+        memory.pitch_slide(2 * 4);
+        assert_eq!(memory.porta(0, false), 0x08);
+    }
+
+    #[test]
+    fn compatible_gxx_separates_porta() {
+        let mut memory = EffectMemory::default();
+        memory.pitch_slide(0x10);
+        assert_eq!(memory.porta(0, true), 0);
+        memory.porta(0x20, true);
+        assert_eq!(memory.pitch_slide(0), 0x10);
+        assert_eq!(memory.porta(0, true), 0x20);
+    }
+
+    #[test]
+    fn volume_column_slides_are_separate() {
+        let mut memory = EffectMemory::default();
+        memory.volume_slide(0x40);
+        assert_eq!(memory.vol_col_slide(0), 0);
+        memory.vol_col_slide(0x05);
+        assert_eq!(memory.volume_slide(0), 0x40);
+        assert_eq!(memory.vol_col_slide(0), 0x05);
+    }
+}
@@ -0,0 +1,1795 @@
+use std::{num::NonZero, ops::ControlFlow};
+
+use crate::{
+    audio_processing::{sample::SamplePlayer, Frame},
+    channel::Pan,
+    file::impulse_format::{
+        instrument::{DuplicateCheckAction, DuplicateCheckType, NewNoteAction},
+        sample::VibratoWave,
+    },
+    manager::PlaybackSettings,
+    project::{
+        event_command::NoteCommand,
+        instrument::Instrument,
+        note_event::{NoteAction, NoteEvent, VolumeEffect},
+        song::Song,
+    },
+};
+
+use background::{BackgroundVoice, BackgroundVoices};
+use memory::EffectMemory;
+
+mod background;
+mod effects;
+mod memory;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlaybackStatus {
+    pub position: PlaybackPosition,
+    /// ticks per row
+    pub speed: NonZero<u8>,
+    pub tempo: NonZero<u8>,
+    /// initialized from the song and changed by Vxx and Wxy
+    pub global_volume: u8,
+    /// initialized from the song and changed by Mxx and Nxy
+    pub channel_volume: [u8; Song::MAX_CHANNELS],
+    /// None if the channel doesn't play an instrument
+    pub envelope_positions: [Option<EnvelopePositions>; Song::MAX_CHANNELS],
+    /// the channel and envelope positions of the background voices that play an instrument.
+    /// Unused entries are at the end
+    pub background_envelope_positions: [Option<(u8, EnvelopePositions)>; BackgroundVoices::MAX],
+    // which sample is playing,
+    // which how far along is each sample
+    // which channel is playing
+    // ...
+}
+
+/// the tick of each envelope of an instrument that a voice is at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EnvelopePositions {
+    pub volume: u16,
+    pub pan: u16,
+    pub pitch: u16,
+}
+
+impl EnvelopePositions {
+    /// None if the voice doesn't play an instrument
+    fn of(voice: &SamplePlayer) -> Option<Self> {
+        voice.instrument()?;
+        let envelopes = voice.envelopes();
+        Some(Self {
+            volume: envelopes.volume.tick(),
+            pan: envelopes.pan.tick(),
+            pitch: envelopes.pitch.tick(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlaybackPosition {
+    /// changes behaviour on pattern end and loop behaviour
+    pub order: Option<u16>,
+    pub pattern: u8,
+    pub row: u16,
+    /// if order is Some this loops the whole song, otherwise it loops the set pattern
+    pub loop_active: bool,
+}
+
+/// Set by Bxx, Cxx and SBx while a row is processed. Applied when the row is finished.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct RowJump {
+    order: Option<u16>,
+    row: Option<u16>,
+    /// stays in the current pattern. Has priority over the other jumps
+    loop_row: Option<u16>,
+}
+
+impl PlaybackPosition {
+    #[inline]
+    fn step_row(&mut self, song: &Song, jump: RowJump) -> ControlFlow<()> {
+        if let Some(loop_row) = jump.loop_row {
+            self.row = loop_row;
+            return ControlFlow::Continue(());
+        }
+
+        let row_count = song.patterns[usize::from(self.pattern)].row_count();
+        if jump == RowJump::default() {
+            self.row += 1;
+            if self.row < row_count {
+                // Pattern not done yet
+                return ControlFlow::Continue(());
+            }
+        }
+        // the pattern is left, either because it is finished or because of a jump
+        let break_row = jump.row.unwrap_or(0);
+
+        let Some(order) = &mut self.order else {
+            // without an orderlist Bxx is ignored and Cxx restarts the pattern at the break row
+            if !self.loop_active {
+                // no looping, pattern is done
+                return ControlFlow::Break(());
+            }
+            self.row = if break_row < row_count { break_row } else { 0 };
+            return ControlFlow::Continue(());
+        };
+
+        let mut next_order = match jump.order {
+            // jumping backwards or to the same order repeats a part of the song, so the song
+            // would never finish. When not looping this is the end of the song.
+            Some(target) if target <= *order && !self.loop_active => return ControlFlow::Break(()),
+            Some(target) => target,
+            None => *order + 1,
+        };
+
+        // next pattern according to song orderlist
+        let pattern = match song.next_pattern(&mut next_order) {
+            // song not finished yet
+            Some(pattern) => pattern,
+            // song is finished
+            None => {
+                if !self.loop_active {
+                    // not looping, therefore break
+                    return ControlFlow::Break(());
+                }
+                // the song should loop
+                // need to check if the song is empty now.
+                next_order = 0;
+                let Some(pattern) = song.next_pattern(&mut next_order) else {
+                    // the song is empty, so playback is stopped
+                    return ControlFlow::Break(());
+                };
+                pattern
+            }
+        };
+
+        *order = next_order;
+        self.pattern = pattern;
+        // same as IT: a break to a row that doesn't exist in the new pattern starts at the first row
+        self.row = if break_row < song.patterns[usize::from(pattern)].row_count() {
+            break_row
+        } else {
+            0
+        };
+        ControlFlow::Continue(())
+    }
+
+    /// if settings specify a pattern pattern always returns Some
+    fn new(settings: PlaybackSettings, song: &Song) -> Option<Self> {
+        match settings {
+            PlaybackSettings::Pattern { idx, should_loop } => {
+                // doesn't panic. patterns len is a constant
+                if idx < u8::try_from(song.patterns.len()).unwrap() {
+                    Some(Self {
+                        order: None,
+                        pattern: idx,
+                        row: 0,
+                        loop_active: should_loop,
+                    })
+                } else {
+                    None
+                }
+            }
+            PlaybackSettings::Order {
+                mut idx,
+                should_loop,
+            } => {
+                let pattern = song.next_pattern(&mut idx)?;
+                Some(Self {
+                    order: Some(idx),
+                    pattern,
+                    row: 0,
+                    loop_active: should_loop,
+                })
+            }
+        }
+    }
+}
+
+/// runtime state of a pattern channel, that isn't part of the song
+#[derive(Debug, Clone, Copy, Default)]
+struct ChannelState {
+    /// set by SB0. reset when a new pattern starts
+    loop_start: u16,
+    /// remaining repetitions of the pattern loop. 0 when no loop is active
+    loop_count: u8,
+    /// starts with the pan of the song channel
+    pan: Pan,
+    /// temporary pan change by panbrello. Reset on every tick
+    pan_modulation: i8,
+    /// starts with the volume of the song channel. ranges 0..=64
+    volume: u8,
+    memory: EffectMemory,
+    /// last sample or instrument played on the channel. Used by notes without one
+    sample: u8,
+    /// set by SAy. The high part of the Oxx offset
+    high_offset: u8,
+    /// shared by Hxy, Uxy, Kxx and volume column H
+    vibrato: Oscillator,
+    tremolo: Oscillator,
+    panbrello: Oscillator,
+}
+
+/// state of the vibrato, tremolo and panbrello effects
+#[derive(Debug, Clone, Copy, Default)]
+struct Oscillator {
+    speed: u8,
+    /// Hxy and Rxy store four times their depth, so that Uxy can be finer. Yxy stores its depth
+    depth: u8,
+    /// position in the waveform. One period is 256 positions
+    position: u8,
+    /// set with S3x, S4x and S5x
+    wave: VibratoWave,
+    /// S3x, S4x and S5x with x >= 4 keep the position when a new note is played
+    keep_position: bool,
+}
+
+impl ChannelState {
+    /// panning commands can't enable a disabled channel
+    fn set_pan(&mut self, pan: Pan) {
+        if !matches!(self.pan, Pan::Disabled) {
+            self.pan = pan;
+        }
+    }
+}
+
+pub struct PlaybackState {
+    position: PlaybackPosition,
+    is_done: bool,
+    // counts up from 0 to speed - 1. 0 is the tick where the row is read
+    tick: u8,
+    // counts down
+    frame: u32,
+    // initialized from the song and changed by Axx and Txx
+    speed: NonZero<u8>,
+    tempo: NonZero<u8>,
+    // initialized from the song and changed by Vxx and Wxy. ranges 0..=128
+    global_volume: u8,
+    jump: RowJump,
+    // set by SEx. the row is played this many extra times
+    row_delay: u8,
+    // how often the current row was already repeated by SEx
+    row_repetition: u8,
+
+    // add current state to support Effects
+    samplerate: NonZero<u32>,
+    // state of the random number generator used by the random waveform
+    random: u32,
+
+    channels: [ChannelState; Song::MAX_CHANNELS],
+
+    /// the voice of each channel
+    voices: [Option<SamplePlayer>; PlaybackState::VOICES],
+    /// voices of past notes that keep playing after a new note on their channel
+    background: BackgroundVoices,
+}
+
+impl PlaybackState {
+    // i don't know yet why those would be different. Splitting them up probably be a bit of work.
+    pub const VOICES: usize = Song::MAX_CHANNELS;
+    /// Txx can't slide or set the tempo outside of 32..=255
+    const MIN_TEMPO: u8 = 0x20;
+    const MAX_CHANNEL_VOLUME: u8 = 64;
+    const MAX_GLOBAL_VOLUME: u8 = 128;
+
+    pub fn iter<'playback, 'song, const INTERPOLATION: u8>(
+        &'playback mut self,
+        song: &'song Song,
+    ) -> PlaybackIter<'song, 'playback, INTERPOLATION> {
+        PlaybackIter { state: self, song }
+    }
+
+    pub(crate) fn frames_per_tick(samplerate: NonZero<u32>, tempo: NonZero<u8>) -> u32 {
+        // don't ask me why times 2. it just does the same as schism now
+        (samplerate.get() * 2) / u32::from(tempo.get())
+    }
+
+    pub fn get_status(&self) -> PlaybackStatus {
+        // maybe if it gets more fields compute them while playing back and just copy out here
+        PlaybackStatus {
+            position: self.position,
+            speed: self.speed,
+            tempo: self.tempo,
+            global_volume: self.global_volume,
+            channel_volume: self.channels.map(|channel| channel.volume),
+            envelope_positions: std::array::from_fn(|idx| {
+                EnvelopePositions::of(self.voices[idx].as_ref()?)
+            }),
+            background_envelope_positions: {
+                let mut background = self.background.iter().filter_map(|voice| {
+                    EnvelopePositions::of(&voice.voice).map(|positions| (voice.channel, positions))
+                });
+                std::array::from_fn(|_| background.next())
+            },
+        }
+    }
+
+    pub fn set_samplerate(&mut self, samplerate: NonZero<u32>) {
+        self.samplerate = samplerate;
+        self.voices
+            .iter_mut()
+            .flatten()
+            .chain(self.background.iter_mut().map(|voice| &mut voice.voice))
+            .for_each(|voice| voice.set_out_samplerate(samplerate));
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.is_done
+    }
+}
+
+impl PlaybackState {
+    /// None if the settings in the order variant don't have any pattern to play
+    pub fn new(song: &Song, samplerate: NonZero<u32>, settings: PlaybackSettings) -> Option<Self> {
+        let position = PlaybackPosition::new(settings, song)?;
+        Some(Self::start(
+            song,
+            samplerate,
+            position,
+            BackgroundVoices::default(),
+        ))
+    }
+
+    /// A playback that is already done. Allocates the background voices, so that
+    /// [restart](Self::restart) can start playing on the audio thread without allocating
+    pub(crate) fn stopped(samplerate: NonZero<u32>) -> Self {
+        Self::idle(samplerate, BackgroundVoices::default())
+    }
+
+    /// Starts a new playback in place of this one. Reuses the background voices, so it doesn't allocate.
+    /// Returns false and leaves self unchanged if the settings don't have any pattern to play
+    pub(crate) fn restart(&mut self, song: &Song, settings: PlaybackSettings) -> bool {
+        let Some(position) = PlaybackPosition::new(settings, song) else {
+            return false;
+        };
+        let mut background = self.background.take();
+        background.clear();
+        *self = Self::start(song, self.samplerate, position, background);
+        true
+    }
+
+    pub(crate) fn stop(&mut self) {
+        self.is_done = true;
+    }
+
+    /// done and without any song values
+    fn idle(samplerate: NonZero<u32>, background: BackgroundVoices) -> Self {
+        Self {
+            position: PlaybackPosition {
+                order: None,
+                pattern: 0,
+                row: 0,
+                loop_active: false,
+            },
+            is_done: true,
+            tick: 0,
+            frame: 0,
+            speed: NonZero::<u8>::MIN,
+            tempo: NonZero::<u8>::MAX,
+            global_volume: 0,
+            jump: RowJump::default(),
+            row_delay: 0,
+            row_repetition: 0,
+            samplerate,
+            random: 0x1234_5678,
+            channels: std::array::from_fn(|_| ChannelState::default()),
+            voices: std::array::from_fn(|_| None),
+            background,
+        }
+    }
+
+    fn start(
+        song: &Song,
+        samplerate: NonZero<u32>,
+        position: PlaybackPosition,
+        background: BackgroundVoices,
+    ) -> Self {
+        let mut out = Self::idle(samplerate, background);
+        out.position = position;
+        out.is_done = false;
+        out.speed = song.initial_speed;
+        out.tempo = song.initial_tempo;
+        out.global_volume = song.global_volume;
+        for (idx, channel) in out.channels.iter_mut().enumerate() {
+            channel.pan = song.pan[idx];
+            channel.volume = song.volume[idx];
+        }
+        // Interpolation not important here. no interpolating is done. only sampledata is copied
+        out.iter::<0>(song).process_tick();
+        out.frame = Self::frames_per_tick(samplerate, out.tempo);
+        out
+    }
+}
+
+impl std::fmt::Debug for PlaybackState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PlaybackState")
+            .field("position", &self.position)
+            .field("tick", &self.tick)
+            .field("frame", &self.frame)
+            .field("speed", &self.speed)
+            .field("tempo", &self.tempo)
+            .field("samplerate", &self.samplerate)
+            .finish_non_exhaustive()?;
+        write!(
+            f,
+            "active channels: {}",
+            self.voices.iter().filter(|v| v.is_some()).count()
+        )?;
+        write!(f, ", background voices: {}", self.background.count())
+    }
+}
+
+pub struct PlaybackIter<'song, 'playback, const INTERPOLATION: u8> {
+    state: &'playback mut PlaybackState,
+    song: &'song Song,
+}
+
+impl<const INTERPOLATION: u8> PlaybackIter<'_, '_, INTERPOLATION> {
+    pub fn frames_per_tick(&self) -> u32 {
+        PlaybackState::frames_per_tick(self.state.samplerate, self.state.tempo)
+    }
+}
+
+impl<const INTERPOLATION: u8> Iterator for PlaybackIter<'_, '_, INTERPOLATION> {
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        /// global and mix volume range 0..=128
+        fn scale_song_vol(vol: u8) -> f32 {
+            f32::from(vol) / 128.
+        }
+
+        if self.state.is_done {
+            return None;
+        }
+
+        debug_assert!(self.state.channels.len() == self.state.voices.len());
+
+        let foreground =
+            self.state
+                .voices
+                .iter_mut()
+                .zip(&self.state.channels)
+                .map(|(slot, state)| {
+                    let pan = match state.pan {
+                        Pan::Value(pan) => {
+                            Pan::Value(pan.saturating_add_signed(state.pan_modulation).min(64))
+                        }
+                        pan => pan,
+                    };
+                    let voice = slot.as_mut()?;
+                    let out = Self::mix_voice(voice, state.volume, pan);
+                    // this logic removes the voices as soon as possible
+                    if out.is_none() || voice.check_position().is_break() {
+                        *slot = None;
+                    }
+                    out
+                });
+        let background = self.state.background.slots().map(|slot| {
+            let background = slot.as_mut()?;
+            let out = Self::mix_voice(&mut background.voice, background.volume, background.pan);
+            if out.is_none() || background.voice.check_position().is_break() {
+                *slot = None;
+            }
+            out
+        });
+        let out: Frame = foreground.chain(background).flatten().sum();
+        self.step();
+        let out_vol =
+            scale_song_vol(self.state.global_volume) * scale_song_vol(self.song.mix_volume);
+        Some(out * out_vol)
+    }
+}
+
+impl<const INTERPOLATION: u8> PlaybackIter<'_, '_, INTERPOLATION> {
+    /// next frame of the voice with the volume and pan of its channel applied.
+    /// None if the voice ended since the last frame, for example by a fade on the last tick
+    fn mix_voice(voice: &mut SamplePlayer, channel_volume: u8, pan: Pan) -> Option<Frame> {
+        /// channel volume ranges 0..=64
+        fn scale_channel_vol(vol: u8) -> f32 {
+            f32::from(vol) / 64.
+        }
+
+        /// scale from 0..=64 to 0°..=90° in radians
+        fn scale_pan(pan: u8) -> f32 {
+            debug_assert!((0..=64).contains(&pan));
+            (pan as f32) * const { (1. / 64.) * (std::f32::consts::FRAC_PI_2) }
+        }
+
+        let voice_vol = voice.volume_factor();
+        // the pan envelope of the voice is applied to the channel pan
+        let pan = match pan {
+            Pan::Value(pan) => Pan::Value(voice.pan(pan)),
+            pan => pan,
+        };
+        let mut out = voice.next::<INTERPOLATION>()?;
+        match pan {
+            Pan::Value(pan) => out.pan_constant_power(scale_pan(pan)),
+            Pan::Surround => out.pan_surround(),
+            Pan::Disabled => (),
+        }
+        Some(out * (voice_vol * scale_channel_vol(channel_volume)))
+    }
+
+    fn step(&mut self) {
+        // the current speed is a bit off from schism tracker. i don't know why, how much or in which direction.
+        if self.state.frame > 0 {
+            self.state.frame -= 1;
+            return;
+        }
+
+        self.state.tick += 1;
+        if self.state.tick >= self.state.speed.get() {
+            self.state.tick = 0;
+            if self.state.row_repetition < self.state.row_delay {
+                // play the row again without triggering the notes
+                self.state.row_repetition += 1;
+            } else {
+                self.state.row_delay = 0;
+                self.state.row_repetition = 0;
+                let jump = std::mem::take(&mut self.state.jump);
+                match self.state.position.step_row(self.song, jump) {
+                    ControlFlow::Continue(_) => {
+                        let new_pattern = jump.loop_row.is_none()
+                            && (jump.order.is_some()
+                                || jump.row.is_some()
+                                || self.state.position.row == 0);
+                        if new_pattern {
+                            self.state.channels.iter_mut().for_each(|channel| {
+                                channel.loop_start = 0;
+                                channel.loop_count = 0;
+                            });
+                        }
+                    }
+                    ControlFlow::Break(_) => {
+                        self.state.is_done = true;
+                        return;
+                    }
+                }
+            }
+        }
+        self.process_tick();
+        // computed after the tick was processed, so tempo changes apply to this tick
+        self.state.frame = self.frames_per_tick();
+    }
+
+    /// triggers the notes and applies the commands of the current row, that are processed on this tick
+    fn process_tick(&mut self) {
+        let song = self.song;
+        let row = &song.patterns[usize::from(self.state.position.pattern)][self.state.position.row];
+        // modulation effects set it again if they are still active
+        let random = &mut self.state.random;
+        let background = self
+            .state
+            .background
+            .iter_mut()
+            .map(|voice| &mut voice.voice);
+        self.state
+            .voices
+            .iter_mut()
+            .flatten()
+            .chain(background)
+            .for_each(|voice| voice.tick(song.instrument(voice.instrument()), random));
+        self.state
+            .channels
+            .iter_mut()
+            .for_each(|channel| channel.pan_modulation = 0);
+        for (position, event) in row {
+            // same as IT: Oxx is remembered even without a note. Stored before the note reads it
+            if let NoteCommand::SetSampleOffset(value) = event.command {
+                if self.state.tick == 0 {
+                    self.state.channels[usize::from(position.channel)]
+                        .memory
+                        .sample_offset(value);
+                }
+            }
+            // notes are only triggered the first time a row is played
+            let note_tick = self.state.row_repetition == 0
+                && self.state.tick == self.note_delay(position.channel, event);
+            if note_tick {
+                self.trigger_note(position.channel, event);
+                self.volume_column_set(position.channel, event.vol);
+            }
+            self.volume_effect(position.channel, event.vol);
+            match event.command {
+                NoteCommand::SetTempo(speed) if self.state.tick == 0 => {
+                    // A00 is ignored
+                    if let Some(speed) = NonZero::new(speed) {
+                        self.state.speed = speed;
+                    }
+                }
+                NoteCommand::TempoChange(tempo) => self.tempo_change(position.channel, tempo),
+                NoteCommand::JumpToOrder(order) if self.state.tick == 0 => {
+                    self.state.jump.order = Some(u16::from(order));
+                }
+                NoteCommand::BreakToRow(row) if self.state.tick == 0 => {
+                    self.state.jump.row = Some(u16::from(row));
+                }
+                NoteCommand::VolumeSlideDown(value) => {
+                    self.volume_slide_command(position.channel, value);
+                }
+                NoteCommand::PitchSlideDown(value) => {
+                    self.pitch_slide_command(position.channel, value, false);
+                }
+                NoteCommand::PitchSlideUp(value) => {
+                    self.pitch_slide_command(position.channel, value, true);
+                }
+                NoteCommand::SlideToNote(value) => self.porta_command(position.channel, value),
+                NoteCommand::Vibrato(value) => {
+                    let channel = usize::from(position.channel);
+                    self.state.channels[channel].vibrato.set(value, 4);
+                    self.vibrato(channel);
+                }
+                NoteCommand::FineVibrato(value) => {
+                    let channel = usize::from(position.channel);
+                    self.state.channels[channel].vibrato.set(value, 1);
+                    self.vibrato(channel);
+                }
+                NoteCommand::VibratoAndVolSlideDown(value) => {
+                    self.vibrato(usize::from(position.channel));
+                    self.volume_slide_command(position.channel, value);
+                }
+                NoteCommand::Tremolo(value) => {
+                    let channel = usize::from(position.channel);
+                    self.state.channels[channel].tremolo.set(value, 4);
+                    self.tremolo(channel);
+                }
+                NoteCommand::Tremor(value) => self.tremor_command(position.channel, value),
+                NoteCommand::Arpeggio(value) => self.arpeggio_command(position.channel, value),
+                NoteCommand::SlideToNoteAndVolSlideDown(value) => {
+                    self.porta_command(position.channel, 0);
+                    self.volume_slide_command(position.channel, value);
+                }
+                // IT ignores values above the max
+                NoteCommand::SetChannelVol(volume)
+                    if self.state.tick == 0 && volume <= PlaybackState::MAX_CHANNEL_VOLUME =>
+                {
+                    self.state.channels[usize::from(position.channel)].volume = volume;
+                }
+                NoteCommand::ChannelVolumeSlideDown(value) => {
+                    self.channel_volume_slide_command(position.channel, value);
+                }
+                NoteCommand::SetGlobalVolume(volume)
+                    if self.state.tick == 0 && volume <= PlaybackState::MAX_GLOBAL_VOLUME =>
+                {
+                    self.state.global_volume = volume;
+                }
+                NoteCommand::GlobalVolumeSlide(value) => {
+                    self.global_volume_slide_command(position.channel, value);
+                }
+                NoteCommand::PanningSlide(value) => self.pan_slide_command(position.channel, value),
+                NoteCommand::SetPanning(value) if self.state.tick == 0 => {
+                    // scale 0..=255 to 0..=64. X80 is center
+                    let pan = (u16::from(value) * 64 / 255) as u8;
+                    self.state.channels[usize::from(position.channel)].set_pan(Pan::Value(pan));
+                }
+                NoteCommand::Panbrello(value) => {
+                    let channel = usize::from(position.channel);
+                    self.state.channels[channel].panbrello.set(value, 1);
+                    self.panbrello(channel);
+                }
+                NoteCommand::RetriggerNote(value) => {
+                    let note_played = note_tick && matches!(event.note, NoteAction::Play(_));
+                    self.retrigger_command(position.channel, value, note_played);
+                }
+                NoteCommand::AlmostEverything(value) => {
+                    self.special_command(position.channel, value);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Txx sets the tempo on the first tick.
+    /// T0x slides down and T1x slides up by x on every tick of the row but the first
+    fn tempo_change(&mut self, channel: u8, value: u8) {
+        let value = self.state.channels[usize::from(channel)]
+            .memory
+            .tempo(value);
+        let tempo = self.state.tempo.get();
+        let new_tempo = match (value >> 4, value & 0xF) {
+            (0, x) if self.state.tick != 0 => tempo.saturating_sub(x),
+            (1, x) if self.state.tick != 0 => tempo.saturating_add(x),
+            (0 | 1, _) => tempo,
+            _ if self.state.tick == 0 => value,
+            _ => tempo,
+        };
+        // always at least MIN_TEMPO, so never zero
+        self.state.tempo = NonZero::new(new_tempo.max(PlaybackState::MIN_TEMPO)).unwrap();
+    }
+
+    /// Sxy commands. Called on every tick
+    fn special_command(&mut self, channel_idx: u8, value: u8) {
+        let tick = self.state.tick;
+        // pattern loops and delays only count the first time a row is played
+        let first_pass = self.state.row_repetition == 0 && tick == 0;
+        let channel = &mut self.state.channels[usize::from(channel_idx)];
+        let value = channel.memory.special(value);
+        match (value >> 4, value & 0xF) {
+            (0xB, 0) if first_pass => channel.loop_start = self.state.position.row,
+            (0xB, x) if first_pass => {
+                if channel.loop_count == 0 {
+                    channel.loop_count = x;
+                } else {
+                    channel.loop_count -= 1;
+                    if channel.loop_count == 0 {
+                        // same as IT: after a finished loop the next loop starts after this row,
+                        // so loops in a row don't repeat each other
+                        channel.loop_start = self.state.position.row + 1;
+                        return;
+                    }
+                }
+                // with multiple loops on one row the last channel decides where to jump
+                self.state.jump.loop_row = Some(channel.loop_start);
+            }
+            // IT only uses the first SEx of a row
+            (0x3, x) if tick == 0 => channel.vibrato.set_wave(x),
+            (0x4, x) if tick == 0 => channel.tremolo.set_wave(x),
+            (0x5, x) if tick == 0 => channel.panbrello.set_wave(x),
+            // same as schism: 0..=15 is mapped to 2..=62
+            (0x8, x) if tick == 0 => channel.set_pan(Pan::Value(x * 4 + 2)),
+            (0x9, 1) if tick == 0 => channel.set_pan(Pan::Surround),
+            // past note actions on the background voices of the channel
+            (0x7, 0) if tick == 0 => self.state.background.cut_channel(channel_idx),
+            (0x7, 1) if tick == 0 => self
+                .state
+                .background
+                .channel(channel_idx)
+                .for_each(|voice| voice.note_off()),
+            (0x7, 2) if tick == 0 => self
+                .state
+                .background
+                .channel(channel_idx)
+                .for_each(|voice| voice.note_fade()),
+            (0xA, y) if tick == 0 => channel.high_offset = y,
+            // same as IT: SC0 is the same as SC1
+            (0xC, x) if tick == x.max(1) => self.state.voices[usize::from(channel_idx)] = None,
+            (0xE, x) if first_pass && self.state.row_delay == 0 => self.state.row_delay = x,
+            _ => (),
+        }
+    }
+
+    /// the tick on which the note of the event is played. Set with SDx, which uses the memory
+    /// of the S commands like all of them. If the delay is longer than the row the note isn't played at all.
+    fn note_delay(&mut self, channel: u8, event: &NoteEvent) -> u8 {
+        let NoteCommand::AlmostEverything(value) = event.command else {
+            return 0;
+        };
+        let value = self.state.channels[usize::from(channel)]
+            .memory
+            .special(value);
+        if value >> 4 == 0xD {
+            value & 0xF
+        } else {
+            0
+        }
+    }
+
+    /// with portamento the note slides the playing voice instead of starting a new one
+    fn is_portamento(event: &NoteEvent) -> bool {
+        matches!(event.vol, VolumeEffect::SlideToNoteWithSpeed(_))
+            || matches!(
+                event.command,
+                NoteCommand::SlideToNote(_) | NoteCommand::SlideToNoteAndVolSlideDown(_)
+            )
+    }
+
+    fn trigger_note(&mut self, channel: u8, event: &NoteEvent) {
+        let voice = &mut self.state.voices[usize::from(channel)];
+        let note = match event.note {
+            NoteAction::Play(note) => note,
+            NoteAction::None => return,
+            NoteAction::Off => {
+                if let Some(voice) = voice {
+                    voice.note_off();
+                }
+                return;
+            }
+            NoteAction::Cut => {
+                *voice = None;
+                return;
+            }
+            NoteAction::Fade => {
+                if let Some(voice) = voice {
+                    voice.note_fade();
+                }
+                return;
+            }
+        };
+        let state = &mut self.state.channels[usize::from(channel)];
+        // same as IT: 0 is no sample, so the last one of the channel is played
+        if event.sample_instr != 0 {
+            state.sample = event.sample_instr;
+        }
+        let pattern_note = note;
+        let sample = self.song.note_sample(state.sample, note);
+        // instruments can play a different note
+        let note = sample.map_or(note, |(note, _)| note);
+        let sample = sample.map(|(_, sample)| sample);
+        if let Some(voice) = self.state.voices[usize::from(channel)]
+            .as_mut()
+            .filter(|_| Self::is_portamento(event))
+        {
+            voice.retarget(note);
+            voice.set_pattern_note(pattern_note);
+            if let Some((meta, sample)) = sample.filter(|_| event.sample_instr != 0) {
+                // with compatible Gxx IT keeps playing the old sample, but the volume is reset either way
+                if !self.song.compatible_gxx {
+                    voice.change_sample(sample.clone(), *meta);
+                }
+                voice.set_volume(meta.default_volume);
+            }
+            return;
+        }
+        if let Some((meta, sample)) = sample {
+            let offset = self.sample_offset(channel, event, sample.len());
+            let mut player = SamplePlayer::with_offset(
+                sample.clone(),
+                *meta,
+                self.state.samplerate,
+                note,
+                offset,
+            );
+            player.set_pattern_note(pattern_note);
+            let sample_instr = self.state.channels[usize::from(channel)].sample;
+            if let Some(instrument) = self.song.instrument(Some(sample_instr)) {
+                player.set_instrument(sample_instr, instrument);
+                if let Some(previous) = &self.state.voices[usize::from(channel)] {
+                    player.carry_envelopes(previous, instrument);
+                }
+                player.update_envelopes(instrument);
+            }
+            let mut previous = self.state.voices[usize::from(channel)].replace(player);
+            self.duplicate_check(channel, &mut previous);
+            if let Some(previous) = previous {
+                self.new_note_action(channel, previous);
+            }
+            let state = &mut self.state.channels[usize::from(channel)];
+            state.vibrato.retrigger();
+            state.tremolo.retrigger();
+        }
+    }
+
+    /// The Duplicate Check of the instrument of the new voice on the channel. The older voices of
+    /// the channel with the same instrument that match the check get the Duplicate Check Action.
+    /// A cut previous voice is set to None
+    fn duplicate_check(&mut self, channel: u8, previous: &mut Option<SamplePlayer>) {
+        let Some(new) = &self.state.voices[usize::from(channel)] else {
+            return;
+        };
+        let Some(instrument) = self.song.instrument(new.instrument()) else {
+            return;
+        };
+        let keep = |old: &mut SamplePlayer| {
+            if !Self::is_duplicate(instrument, new, old) {
+                return true;
+            }
+            match instrument.duplicate_check_action {
+                DuplicateCheckAction::Cut => return false,
+                DuplicateCheckAction::NoteOff => old.note_off(),
+                DuplicateCheckAction::NoteFade => old.note_fade(),
+            }
+            true
+        };
+        if previous.as_mut().is_some_and(|voice| !keep(voice)) {
+            *previous = None;
+        }
+        self.state.background.retain_channel(channel, keep);
+    }
+
+    /// only voices of the same instrument are duplicates
+    fn is_duplicate(instrument: &Instrument, new: &SamplePlayer, old: &SamplePlayer) -> bool {
+        old.instrument() == new.instrument()
+            && match instrument.duplicate_check_type {
+                DuplicateCheckType::Off => false,
+                DuplicateCheckType::Note => old.pattern_note() == new.pattern_note(),
+                DuplicateCheckType::Sample => old.plays_same_sample(new),
+                DuplicateCheckType::Instrument => true,
+            }
+    }
+
+    /// The New Note Action of the instrument of the previous voice decides if it keeps playing
+    /// in the background. Voices without an instrument are cut
+    fn new_note_action(&mut self, channel: u8, mut voice: SamplePlayer) {
+        let Some(instrument) = self.song.instrument(voice.instrument()) else {
+            return;
+        };
+        match instrument.new_note_action {
+            NewNoteAction::Cut => return,
+            NewNoteAction::Continue => (),
+            NewNoteAction::NoteOff => voice.note_off(),
+            NewNoteAction::NoteFade => voice.note_fade(),
+        }
+        let state = &self.state.channels[usize::from(channel)];
+        self.state.background.push(BackgroundVoice {
+            channel,
+            voice,
+            volume: state.volume,
+            pan: state.pan,
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZero;
+
+    use crate::{
+        audio_processing::{
+            playback::{
+                background::{BackgroundVoice, BackgroundVoices},
+                PlaybackPosition, PlaybackState, RowJump,
+            },
+            sample::SamplePlayer,
+            Frame,
+        },
+        channel::Pan,
+        file::impulse_format::{
+            header::PatternOrder,
+            instrument::{DuplicateCheckAction, DuplicateCheckType, NewNoteAction},
+        },
+        manager::PlaybackSettings,
+        project::{
+            event_command::NoteCommand,
+            instrument::Instrument,
+            note_event::{Note, NoteAction, NoteEvent, VolumeEffect},
+            pattern::{InPatternPosition, Pattern},
+            song::Song,
+        },
+        sample::{Sample, SampleMetaData},
+    };
+
+    const SAMPLERATE: NonZero<u32> = NonZero::new(44_100).unwrap();
+
+    fn sample() -> (SampleMetaData, Sample) {
+        let meta = SampleMetaData {
+            default_volume: 64,
+            global_volume: 64,
+            default_pan: None,
+            vibrato_speed: 0,
+            vibrato_depth: 0,
+            vibrato_rate: 0,
+            vibrato_waveform: Default::default(),
+            sample_rate: NonZero::new(8363).unwrap(),
+            base_note: Note::default(),
+            sample_loop: None,
+            sustain_loop: None,
+        };
+        (meta, Sample::new_mono(std::iter::repeat_n(0.5, 100_000)))
+    }
+
+    /// a song with a single pattern and one row per tick
+    fn pattern_song(events: &[(u16, u8, NoteEvent)]) -> Song {
+        let mut song = Song::default();
+        song.pattern_order[0] = PatternOrder::Number(0);
+        song.initial_speed = NonZero::new(1).unwrap();
+        song.samples[1] = Some(sample());
+        for (row, channel, event) in events {
+            let position = InPatternPosition {
+                row: *row,
+                channel: *channel,
+            };
+            song.patterns[0].set_event(position, *event);
+        }
+        song
+    }
+
+    /// instrument 1 plays every note with sample 1
+    fn instrument_song(events: &[(u16, u8, NoteEvent)], mut instrument: Instrument) -> Song {
+        let mut song = pattern_song(events);
+        song.instrument_mode = true;
+        for (_, sample) in &mut instrument.note_sample_table {
+            *sample = 1;
+        }
+        song.instruments[1] = Some(instrument);
+        song
+    }
+
+    fn command(command: NoteCommand) -> NoteEvent {
+        NoteEvent {
+            command,
+            ..Default::default()
+        }
+    }
+
+    fn note(note: u8, command: NoteCommand) -> NoteEvent {
+        NoteEvent {
+            note: NoteAction::Play(Note::new(note).unwrap()),
+            sample_instr: 1,
+            command,
+            ..Default::default()
+        }
+    }
+
+    fn start(song: &Song) -> PlaybackState {
+        let settings = PlaybackSettings::Pattern {
+            idx: 0,
+            should_loop: false,
+        };
+        PlaybackState::new(song, SAMPLERATE, settings).unwrap()
+    }
+
+    /// processes the next tick right away
+    fn next_tick(state: &mut PlaybackState, song: &Song) {
+        state.frame = 0;
+        state.iter::<0>(song).step();
+    }
+
+    /// the rows in the order they are played. Repeated rows show up again
+    /// mixes the next ticks like the audio thread does
+    fn render(state: &mut PlaybackState, song: &Song, ticks: u32) -> Vec<Frame> {
+        let frames = PlaybackState::frames_per_tick(SAMPLERATE, state.tempo) * ticks;
+        state.iter::<0>(song).take(frames as usize).collect()
+    }
+
+    /// a value of the voice on channel 0 for each tick, starting with the first one
+    fn per_tick<T>(song: &Song, ticks: usize, value: impl Fn(&SamplePlayer) -> T) -> Vec<T> {
+        let mut state = start(song);
+        let mut out = Vec::new();
+        for _ in 0..ticks {
+            out.push(value(state.voices[0].as_ref().unwrap()));
+            next_tick(&mut state, song);
+        }
+        out
+    }
+
+    /// a note with the command on the first row, played at the speed
+    fn command_song(command: NoteCommand, speed: u8) -> Song {
+        let mut song = pattern_song(&[(0, 0, note(60, command))]);
+        song.initial_speed = NonZero::new(speed).unwrap();
+        song
+    }
+
+    fn played_rows(song: &Song, rows: usize) -> Vec<u16> {
+        let mut state = start(song);
+        let mut out = vec![state.position.row];
+        while out.len() < rows {
+            next_tick(&mut state, song);
+            if state.tick == 0 {
+                out.push(state.position.row);
+            }
+        }
+        out
+    }
+
+    fn three_order_song() -> Song {
+        let mut song = Song::default();
+        for i in 0..3 {
+            song.pattern_order[usize::from(i)] = PatternOrder::Number(i);
+        }
+        song.patterns[1] = Pattern::new(32);
+        song
+    }
+
+    fn position(order: u16, row: u16, loop_active: bool) -> PlaybackPosition {
+        PlaybackPosition {
+            order: Some(order),
+            pattern: u8::try_from(order).unwrap(),
+            row,
+            loop_active,
+        }
+    }
+
+    #[test]
+    fn jump_and_break() {
+        let song = three_order_song();
+
+        let mut pos = position(0, 3, false);
+        let jump = RowJump {
+            order: Some(2),
+            row: Some(10),
+            ..Default::default()
+        };
+        assert!(pos.step_row(&song, jump).is_continue());
+        assert_eq!(pos, position(2, 10, false));
+
+        // pattern 1 only has 32 rows
+        let mut pos = position(0, 3, false);
+        let jump = RowJump {
+            order: None,
+            row: Some(40),
+            ..Default::default()
+        };
+        assert!(pos.step_row(&song, jump).is_continue());
+        assert_eq!(pos, position(1, 0, false));
+    }
+
+    #[test]
+    fn jump_backwards_loops_song() {
+        let song = three_order_song();
+        let jump = RowJump {
+            order: Some(0),
+            ..Default::default()
+        };
+
+        let mut pos = position(2, 3, false);
+        assert!(pos.step_row(&song, jump).is_break());
+
+        let mut pos = position(2, 3, true);
+        assert!(pos.step_row(&song, jump).is_continue());
+        assert_eq!(pos, position(0, 0, true));
+    }
+
+    #[test]
+    fn volume_column_waits_for_delayed_note() {
+        let mut song = pattern_song(&[
+            (0, 0, note(60, NoteCommand::None)),
+            (
+                1,
+                0,
+                NoteEvent {
+                    vol: VolumeEffect::Volume(10),
+                    ..note(62, NoteCommand::AlmostEverything(0xD2))
+                },
+            ),
+        ]);
+        song.initial_speed = NonZero::new(4).unwrap();
+        let mut state = start(&song);
+        for _ in 0..4 {
+            next_tick(&mut state, &song);
+        }
+        // row 1, the old note keeps its volume
+        assert_eq!(state.voices[0].as_ref().unwrap().volume(), 64);
+        next_tick(&mut state, &song);
+        next_tick(&mut state, &song);
+        let voice = state.voices[0].as_ref().unwrap();
+        assert_eq!((voice.note().get(), voice.volume()), (62, 10));
+    }
+
+    #[test]
+    fn volume_column_clamps_values() {
+        let effect = |vol| NoteEvent {
+            vol,
+            ..Default::default()
+        };
+        let mut song = pattern_song(&[
+            (
+                0,
+                0,
+                NoteEvent {
+                    vol: VolumeEffect::VolSlideDown(200),
+                    ..note(60, NoteCommand::None)
+                },
+            ),
+            (1, 0, effect(VolumeEffect::FineVolSlideDown(255))),
+            (2, 0, effect(VolumeEffect::PitchSlideUp(255))),
+            (3, 0, effect(VolumeEffect::PitchSlideDown(255))),
+            (
+                4,
+                0,
+                NoteEvent {
+                    vol: VolumeEffect::SlideToNoteWithSpeed(200),
+                    ..note(72, NoteCommand::None)
+                },
+            ),
+            (5, 0, effect(VolumeEffect::VibratoWithSpeed(255))),
+        ]);
+        song.initial_speed = NonZero::new(4).unwrap();
+        let mut state = start(&song);
+        for _ in 0..3 {
+            next_tick(&mut state, &song);
+        }
+        // the slide is clamped to D9
+        assert_eq!(state.voices[0].as_ref().unwrap().volume(), 64 - 3 * 9);
+        next_tick(&mut state, &song);
+        assert_eq!(state.voices[0].as_ref().unwrap().volume(), 64 - 4 * 9);
+        for _ in 0..4 * 5 {
+            next_tick(&mut state, &song);
+        }
+        // G9 is the fastest portamento and reaches the note within the row
+        assert_eq!(state.voices[0].as_ref().unwrap().note().get(), 72);
+    }
+
+    fn offset_song(events: &[(u16, u8, NoteEvent)], old_effects: bool) -> PlaybackState {
+        let mut song = pattern_song(events);
+        song.old_effects = old_effects;
+        let mut state = start(&song);
+        next_tick(&mut state, &song);
+        state
+    }
+
+    #[test]
+    fn keys_without_sample_are_silent() {
+        let mut song = pattern_song(&[
+            (0, 0, note(60, NoteCommand::None)),
+            (0, 1, note(61, NoteCommand::None)),
+        ]);
+        song.instrument_mode = true;
+        // sample 0 must not be played, even if the slot is used
+        song.samples[0] = Some(sample());
+        let mut instrument = Instrument::default();
+        instrument.note_sample_table[60].1 = 1;
+        song.instruments[1] = Some(instrument);
+        let mut state = start(&song);
+        next_tick(&mut state, &song);
+        assert!(state.voices[0].is_some());
+        assert!(state.voices[1].is_none());
+    }
+
+    #[test]
+    fn status_includes_background_voices() {
+        let instrument = Instrument {
+            new_note_action: NewNoteAction::Continue,
+            ..Default::default()
+        };
+        let song = instrument_song(
+            &[
+                (0, 0, note(60, NoteCommand::None)),
+                (1, 0, note(62, NoteCommand::None)),
+            ],
+            instrument,
+        );
+        let mut state = start(&song);
+        next_tick(&mut state, &song);
+        next_tick(&mut state, &song);
+        let status = state.get_status();
+        let foreground = status.envelope_positions[0].unwrap();
+        let (channel, background) = status.background_envelope_positions[0].unwrap();
+        assert_eq!(channel, 0);
+        // the background voice started a tick earlier
+        assert_eq!(background.volume, foreground.volume + 1);
+        assert!(status.background_envelope_positions[1].is_none());
+    }
+
+    fn background_voice(channel: u8, volume: u8) -> BackgroundVoice {
+        let (meta, sample) = sample();
+        BackgroundVoice {
+            channel,
+            voice: SamplePlayer::new(sample, meta, SAMPLERATE, Note::default()),
+            volume,
+            pan: Pan::default(),
+        }
+    }
+
+    #[test]
+    fn full_background_steals_quietest_voice() {
+        let mut background = BackgroundVoices::default();
+        for idx in 0..BackgroundVoices::MAX {
+            let volume = if idx == 100 { 10 } else { 64 };
+            background.push(background_voice(0, volume));
+        }
+        background.push(background_voice(1, 32));
+        assert_eq!(background.count(), BackgroundVoices::MAX);
+        assert!(background.iter().all(|voice| voice.volume != 10));
+        assert_eq!(background.channel(1).count(), 1);
+    }
+
+    /// two notes on channel 0 and channel 1, the first ones are pushed into the background.
+    /// The fadeout keeps fading voices alive for a while
+    fn background_song(new_note_action: NewNoteAction, channel_0_row_2: NoteEvent) -> Song {
+        let instrument = Instrument {
+            new_note_action,
+            fadeout: 1,
+            ..Default::default()
+        };
+        instrument_song(
+            &[
+                (0, 0, note(60, NoteCommand::None)),
+                (0, 1, note(60, NoteCommand::None)),
+                (1, 0, note(62, NoteCommand::None)),
+                (1, 1, note(62, NoteCommand::None)),
+                (2, 0, channel_0_row_2),
+            ],
+            instrument,
+        )
+    }
+
+    #[test]
+    fn new_note_actions() {
+        let background = |action| {
+            let song = background_song(action, NoteEvent::default());
+            let mut state = start(&song);
+            next_tick(&mut state, &song);
+            let voice = state.background.channel(0).next().map(|voice| {
+                assert_eq!(voice.note().get(), 60);
+                (voice.is_released(), voice.is_fading())
+            });
+            assert_eq!(state.background.count(), usize::from(voice.is_some()) * 2);
+            voice
+        };
+        assert_eq!(background(NewNoteAction::Cut), None);
+        assert_eq!(background(NewNoteAction::Continue), Some((false, false)));
+        // without a volume envelope note off also fades, like in IT
+        assert_eq!(background(NewNoteAction::NoteOff), Some((true, true)));
+        assert_eq!(background(NewNoteAction::NoteFade), Some((false, true)));
+    }
+
+    #[test]
+    fn restart_reuses_background() {
+        let song = background_song(NewNoteAction::Continue, NoteEvent::default());
+        let mut state = start(&song);
+        next_tick(&mut state, &song);
+        assert_eq!(state.background.count(), 2);
+        let settings = PlaybackSettings::Pattern {
+            idx: 0,
+            should_loop: false,
+        };
+        assert!(state.restart(&song, settings));
+        assert_eq!((state.position.row, state.background.count()), (0, 0));
+        next_tick(&mut state, &song);
+        assert_eq!(state.background.count(), 2);
+    }
+
+    #[test]
+    fn stopped_playback_restarts() {
+        let song = pattern_song(&[(0, 0, note(60, NoteCommand::None))]);
+        let mut state = PlaybackState::stopped(SAMPLERATE);
+        assert_eq!(state.iter::<0>(&song).next(), None);
+        let settings = PlaybackSettings::Pattern {
+            idx: 0,
+            should_loop: false,
+        };
+        assert!(state.restart(&song, settings));
+        assert!(render(&mut state, &song, 1)[0] != Frame::default());
+        state.stop();
+        assert_eq!(state.iter::<0>(&song).next(), None);
+    }
+
+    #[test]
+    fn past_note_actions_only_affect_their_channel() {
+        let background = |value| {
+            let song = background_song(
+                NewNoteAction::Continue,
+                command(NoteCommand::AlmostEverything(value)),
+            );
+            let mut state = start(&song);
+            next_tick(&mut state, &song);
+            next_tick(&mut state, &song);
+            let other = state.background.channel(1).next().unwrap();
+            assert!(!other.is_released() && !other.is_fading());
+            let voice = state.background.channel(0).next();
+            voice.map(|voice| (voice.is_released(), voice.is_fading()))
+        };
+        assert_eq!(background(0x70), None);
+        assert_eq!(background(0x71), Some((true, true)));
+        assert_eq!(background(0x72), Some((false, true)));
+    }
+
+    /// two notes after each other on channel 0. The first one is kept by the New Note Action,
+    /// so the result is its state after the duplicate check: None if cut, else released and fading
+    fn duplicate_check(
+        check: DuplicateCheckType,
+        action: DuplicateCheckAction,
+        first: u8,
+        second: u8,
+    ) -> Option<(bool, bool)> {
+        let instrument = Instrument {
+            new_note_action: NewNoteAction::Continue,
+            duplicate_check_type: check,
+            duplicate_check_action: action,
+            fadeout: 1,
+            ..Default::default()
+        };
+        let mut song = instrument_song(
+            &[
+                (0, 0, note(first, NoteCommand::None)),
+                (1, 0, note(second, NoteCommand::None)),
+            ],
+            instrument,
+        );
+        song.samples[2] = Some(sample());
+        let table = &mut song.instruments[1].as_mut().unwrap().note_sample_table;
+        // different notes in the pattern that play the same note
+        table[60].0 = Note::new(72).unwrap();
+        table[61].0 = Note::new(72).unwrap();
+        table[62].1 = 2;
+        let mut state = start(&song);
+        next_tick(&mut state, &song);
+        let voice = state.background.channel(0).next();
+        voice.map(|voice| (voice.is_released(), voice.is_fading()))
+    }
+
+    #[test]
+    fn duplicate_check_types() {
+        let cut = |check, first, second| {
+            duplicate_check(check, DuplicateCheckAction::Cut, first, second).is_none()
+        };
+        assert!(!cut(DuplicateCheckType::Off, 60, 60));
+        assert!(cut(DuplicateCheckType::Note, 60, 60));
+        // the pattern note is compared, not the one the instrument plays
+        assert!(!cut(DuplicateCheckType::Note, 60, 61));
+        assert!(cut(DuplicateCheckType::Sample, 60, 61));
+        assert!(!cut(DuplicateCheckType::Sample, 60, 62));
+        assert!(cut(DuplicateCheckType::Instrument, 60, 62));
+    }
+
+    #[test]
+    fn duplicate_check_actions() {
+        let check = |action| duplicate_check(DuplicateCheckType::Instrument, action, 60, 60);
+        assert_eq!(check(DuplicateCheckAction::Cut), None);
+        assert_eq!(check(DuplicateCheckAction::NoteOff), Some((true, true)));
+        assert_eq!(check(DuplicateCheckAction::NoteFade), Some((false, true)));
+    }
+
+    #[test]
+    fn sample_offset() {
+        // O20 without a note is remembered
+        let state = offset_song(
+            &[
+                (0, 0, command(NoteCommand::SetSampleOffset(0x20))),
+                (1, 0, note(60, NoteCommand::SetSampleOffset(0))),
+            ],
+            false,
+        );
+        assert_eq!(state.voices[0].as_ref().unwrap().position(), 0x2000);
+
+        // SA1 and O20 combine to 0x12000
+        let state = offset_song(
+            &[
+                (0, 0, command(NoteCommand::AlmostEverything(0xA1))),
+                (1, 0, note(60, NoteCommand::SetSampleOffset(0x20))),
+            ],
+            false,
+        );
+        assert_eq!(state.voices[0].as_ref().unwrap().position(), 0x12000);
+    }
+
+    #[test]
+    fn sample_offset_past_the_end() {
+        let events = [
+            (0, 0, command(NoteCommand::AlmostEverything(0xA2))),
+            (1, 0, note(60, NoteCommand::SetSampleOffset(0x20))),
+        ];
+        // IT ignores the offset
+        let state = offset_song(&events, false);
+        assert_eq!(state.voices[0].as_ref().unwrap().position(), 0);
+        // old effects play from the end
+        let state = offset_song(&events, true);
+        assert_eq!(state.voices[0].as_ref().unwrap().position(), 100_000);
+    }
+
+    #[test]
+    fn note_delay_uses_memory() {
+        let mut song = pattern_song(&[
+            (0, 0, note(60, NoteCommand::AlmostEverything(0xD2))),
+            (1, 0, note(62, NoteCommand::AlmostEverything(0))),
+        ]);
+        song.initial_speed = NonZero::new(4).unwrap();
+        let mut state = start(&song);
+        for _ in 0..5 {
+            next_tick(&mut state, &song);
+        }
+        // S00 repeats SD2, so the note of row 1 isn't played yet
+        assert_eq!(state.voices[0].as_ref().unwrap().note().get(), 60);
+        next_tick(&mut state, &song);
+        assert_eq!(state.voices[0].as_ref().unwrap().note().get(), 62);
+    }
+
+    #[test]
+    fn pattern_loop_repeats() {
+        // SB0 on row 1, SB2 on row 3
+        let song = pattern_song(&[
+            (1, 0, command(NoteCommand::AlmostEverything(0xB0))),
+            (3, 0, command(NoteCommand::AlmostEverything(0xB2))),
+        ]);
+        assert_eq!(played_rows(&song, 10), [0, 1, 2, 3, 1, 2, 3, 1, 2, 3]);
+        assert_eq!(played_rows(&song, 12)[10..], [4, 5]);
+    }
+
+    #[test]
+    fn pattern_loop_without_start_loops_from_pattern_start() {
+        let song = pattern_song(&[(1, 0, command(NoteCommand::AlmostEverything(0xB1)))]);
+        assert_eq!(played_rows(&song, 5), [0, 1, 0, 1, 2]);
+    }
+
+    #[test]
+    fn pattern_loops_on_multiple_channels() {
+        // each channel keeps its own loop
+        let song = pattern_song(&[
+            (0, 0, command(NoteCommand::AlmostEverything(0xB0))),
+            (1, 0, command(NoteCommand::AlmostEverything(0xB1))),
+            (2, 1, command(NoteCommand::AlmostEverything(0xB0))),
+            (3, 1, command(NoteCommand::AlmostEverything(0xB1))),
+        ]);
+        assert_eq!(played_rows(&song, 9), [0, 1, 0, 1, 2, 3, 2, 3, 4]);
+
+        // same as IT: with loops ending on the same row the last channel decides where to jump
+        let song = pattern_song(&[
+            (0, 0, command(NoteCommand::AlmostEverything(0xB0))),
+            (1, 1, command(NoteCommand::AlmostEverything(0xB0))),
+            (2, 0, command(NoteCommand::AlmostEverything(0xB1))),
+            (2, 1, command(NoteCommand::AlmostEverything(0xB1))),
+        ]);
+        assert_eq!(played_rows(&song, 6), [0, 1, 2, 1, 2, 3]);
+    }
+
+    #[test]
+    fn row_delay_doesnt_retrigger() {
+        let song = pattern_song(&[(0, 0, note(60, NoteCommand::AlmostEverything(0xE2)))]);
+        assert_eq!(played_rows(&song, 4), [0, 0, 0, 1]);
+
+        let mut state = start(&song);
+        state.voices[0].as_mut().unwrap().set_volume(10);
+        next_tick(&mut state, &song);
+        next_tick(&mut state, &song);
+        // a retriggered note would start at the default volume again
+        assert_eq!(state.voices[0].as_ref().unwrap().volume(), 10);
+    }
+
+    #[test]
+    fn note_actions_stop_the_channel_voice() {
+        let action = |note| NoteEvent {
+            note,
+            ..Default::default()
+        };
+        let instrument = Instrument {
+            fadeout: 1,
+            ..Default::default()
+        };
+        let song = instrument_song(
+            &[
+                (0, 0, note(60, NoteCommand::None)),
+                (0, 1, note(60, NoteCommand::None)),
+                (0, 2, note(60, NoteCommand::None)),
+                (1, 0, action(NoteAction::Off)),
+                (1, 1, action(NoteAction::Cut)),
+                (1, 2, action(NoteAction::Fade)),
+            ],
+            instrument,
+        );
+        let mut state = start(&song);
+        assert!(state.voices[..3]
+            .iter()
+            .flatten()
+            .all(|voice| !voice.is_released()));
+        next_tick(&mut state, &song);
+        // without a volume envelope the release fades the note as well
+        let released = state.voices[0].as_ref().unwrap();
+        assert!(released.is_released() && released.is_fading());
+        assert!(state.voices[1].is_none());
+        let faded = state.voices[2].as_ref().unwrap();
+        assert!(faded.is_fading() && !faded.is_released());
+    }
+
+    #[test]
+    fn faded_voice_ends_while_rendering() {
+        // without an instrument the fade ends the note on the next tick
+        let song = pattern_song(&[
+            (0, 0, note(60, NoteCommand::None)),
+            (
+                1,
+                0,
+                NoteEvent {
+                    note: NoteAction::Fade,
+                    ..Default::default()
+                },
+            ),
+        ]);
+        let mut state = start(&song);
+        let frames = render(&mut state, &song, 3);
+        assert!(frames[0] != Frame::default());
+        assert_eq!(frames.last(), Some(&Frame::default()));
+        assert!(state.voices[0].is_none());
+    }
+
+    #[test]
+    fn tempo_slides_after_the_first_tick() {
+        fn tempos(song: &Song, ticks: usize) -> Vec<u8> {
+            let mut state = start(song);
+            let mut out = Vec::new();
+            for _ in 0..ticks {
+                out.push(state.tempo.get());
+                next_tick(&mut state, song);
+            }
+            out
+        }
+
+        let mut song = pattern_song(&[
+            (0, 0, note(60, NoteCommand::TempoChange(0x13))),
+            // T00 slides with the memory
+            (1, 0, command(NoteCommand::TempoChange(0x00))),
+        ]);
+        song.initial_speed = NonZero::new(3).unwrap();
+        assert_eq!(tempos(&song, 6), [125, 128, 131, 131, 134, 137]);
+
+        // the tempo doesn't go below 0x20
+        song.initial_tempo = NonZero::new(0x22).unwrap();
+        song.patterns[0].set_event(
+            InPatternPosition { row: 0, channel: 0 },
+            note(60, NoteCommand::TempoChange(0x05)),
+        );
+        assert_eq!(tempos(&song, 3), [0x22, 0x20, 0x20]);
+
+        song.patterns[0].set_event(
+            InPatternPosition { row: 0, channel: 0 },
+            note(60, NoteCommand::TempoChange(0x40)),
+        );
+        assert_eq!(tempos(&song, 3), [0x40, 0x40, 0x40]);
+    }
+
+    #[test]
+    fn pitch_slides_per_tick() {
+        let offsets =
+            |command| per_tick(&command_song(command, 3), 3, |voice| voice.pitch_offset());
+        assert_eq!(offsets(NoteCommand::PitchSlideUp(0x02)), [0., 8., 16.]);
+        assert_eq!(offsets(NoteCommand::PitchSlideDown(0x02)), [0., -8., -16.]);
+        // fine and extra fine slides only happen on the first tick
+        assert_eq!(offsets(NoteCommand::PitchSlideUp(0xF2)), [8.; 3]);
+        assert_eq!(offsets(NoteCommand::PitchSlideDown(0xE2)), [-2.; 3]);
+
+        // amiga slides change the period by 4 * x. C-5 of the test sample has a period of 1712
+        let mut song = command_song(NoteCommand::PitchSlideDown(0x02), 3);
+        song.linear_slides = false;
+        let offsets = per_tick(&song, 3, |voice| voice.pitch_offset());
+        for (offset, period) in offsets.into_iter().zip([1712., 1720., 1728.]) {
+            let expected = (1712f32 / period).log2() * 768.;
+            assert!((offset - expected).abs() < 1e-3, "{offset} {expected}");
+        }
+    }
+
+    #[test]
+    fn vibrato_and_tremolo_waveforms() {
+        // the waveform is set on row 0, then the effect runs on row 1 and with a new note on row 2
+        fn song(waveform: u8, effect: NoteCommand) -> Song {
+            let mut song = pattern_song(&[
+                (0, 0, note(60, NoteCommand::AlmostEverything(waveform))),
+                (1, 0, command(effect)),
+                (2, 0, note(60, effect)),
+            ]);
+            song.initial_speed = NonZero::new(4).unwrap();
+            song
+        }
+        let vibrato = |waveform| {
+            let song = song(waveform, NoteCommand::Vibrato(0x41));
+            per_tick(&song, 12, |voice| voice.pitch_modulation())[4..].to_vec()
+        };
+        let tremolo = |waveform| {
+            let song = song(waveform, NoteCommand::Tremolo(0x4F));
+            per_tick(&song, 12, |voice| voice.volume_modulation())[4..].to_vec()
+        };
+
+        // ramp down and square. The new note restarts the waveform
+        assert_eq!(vibrato(0x31), [8., 7., 6., 5., 8., 7., 6., 5.]);
+        assert_eq!(vibrato(0x32), [8.; 8]);
+        assert_eq!(tremolo(0x41), [30, 26, 22, 18, 30, 26, 22, 18]);
+        assert_eq!(tremolo(0x42), [30; 8]);
+        // S35 and S45 keep the position on a new note
+        assert_eq!(vibrato(0x35), [8., 7., 6., 5., 4., 3., 2., 1.]);
+        assert_eq!(tremolo(0x45), [30, 26, 22, 18, 15, 11, 7, 3]);
+    }
+
+    #[test]
+    fn channel_and_global_volume_limits() {
+        let mut song = pattern_song(&[
+            (0, 0, note(60, NoteCommand::SetChannelVol(0x30))),
+            (0, 1, command(NoteCommand::SetGlobalVolume(0x7C))),
+            (1, 0, command(NoteCommand::ChannelVolumeSlideDown(0x80))),
+            (1, 1, command(NoteCommand::GlobalVolumeSlide(0x40))),
+            // both slides stop at the max
+            (2, 0, command(NoteCommand::ChannelVolumeSlideDown(0x80))),
+            (2, 1, command(NoteCommand::GlobalVolumeSlide(0x40))),
+            // values above the max are ignored
+            (3, 0, command(NoteCommand::SetChannelVol(0x41))),
+            (3, 1, command(NoteCommand::SetGlobalVolume(0x81))),
+            (4, 0, command(NoteCommand::ChannelVolumeSlideDown(0x0F))),
+            (4, 1, command(NoteCommand::GlobalVolumeSlide(0x0F))),
+            (5, 0, command(NoteCommand::ChannelVolumeSlideDown(0x0F))),
+            // the channel volume stops at 0
+            (6, 0, command(NoteCommand::ChannelVolumeSlideDown(0x00))),
+        ]);
+        song.initial_speed = NonZero::new(3).unwrap();
+        let mut state = start(&song);
+        let mut volumes = Vec::new();
+        for _ in 0..21 {
+            volumes.push((state.channels[0].volume, state.global_volume));
+            next_tick(&mut state, &song);
+        }
+        let (channel, global): (Vec<_>, Vec<_>) = volumes.into_iter().unzip();
+        assert_eq!(channel[..9], [48, 48, 48, 48, 56, 64, 64, 64, 64]);
+        assert_eq!(channel[9..], [64, 64, 64, 64, 49, 34, 34, 19, 4, 4, 0, 0]);
+        assert_eq!(global[..9], [124, 124, 124, 124, 128, 128, 128, 128, 128]);
+        assert_eq!(global[9..15], [128, 128, 128, 128, 113, 98]);
+    }
+
+    #[test]
+    fn pan_commands() {
+        let mut song = pattern_song(&[
+            // X80 is center
+            (0, 0, note(60, NoteCommand::SetPanning(0x80))),
+            (1, 0, command(NoteCommand::PanningSlide(0x40))),
+            (2, 0, command(NoteCommand::PanningSlide(0x02))),
+            (3, 0, command(NoteCommand::AlmostEverything(0x8F))),
+            (4, 0, command(NoteCommand::SetPanning(0xFF))),
+            (5, 0, command(NoteCommand::PanningSlide(0x01))),
+            (6, 0, command(NoteCommand::AlmostEverything(0x80))),
+            (7, 0, command(NoteCommand::SetPanning(0x00))),
+            (8, 0, command(NoteCommand::PanningSlide(0x10))),
+        ]);
+        song.initial_speed = NonZero::new(3).unwrap();
+        let mut state = start(&song);
+        let mut pans = Vec::new();
+        for _ in 0..27 {
+            let Pan::Value(pan) = state.channels[0].pan else {
+                panic!()
+            };
+            pans.push(pan);
+            next_tick(&mut state, &song);
+        }
+        // P40 slides left and P02 right
+        assert_eq!(pans[..9], [32, 32, 32, 32, 28, 24, 24, 26, 28]);
+        // S8F and XFF are right, the slide stops at the side
+        assert_eq!(pans[9..15], [62, 62, 62, 64, 64, 64]);
+        assert_eq!(pans[15..18], [64, 64, 64]);
+        // S80 and X00 are left
+        assert_eq!(pans[18..], [2, 2, 2, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn tone_portamento_stops_at_note() {
+        let mut song = pattern_song(&[
+            (0, 0, note(60, NoteCommand::None)),
+            (1, 0, note(62, NoteCommand::SlideToNote(0x08))),
+            // continues the portamento and slides the volume down by 1
+            (2, 0, command(NoteCommand::SlideToNoteAndVolSlideDown(0x01))),
+            (3, 0, command(NoteCommand::SlideToNote(0))),
+        ]);
+        song.initial_speed = NonZero::new(3).unwrap();
+        let mut state = start(&song);
+        next_tick(&mut state, &song);
+        next_tick(&mut state, &song);
+        let mut offsets = Vec::new();
+        for _ in 0..9 {
+            next_tick(&mut state, &song);
+            offsets.push(state.voices[0].as_ref().unwrap().pitch_offset());
+        }
+        // two semitones are 128 steps, every tick but the first slides by 4 * 8
+        assert_eq!(offsets, [-128., -96., -64., -64., -32., 0., 0., 0., 0.]);
+        let voice = state.voices[0].as_ref().unwrap();
+        assert_eq!((voice.note().get(), voice.volume()), (62, 62));
+    }
+
+    #[test]
+    fn compatible_gxx_keeps_the_sample() {
+        let play = |compatible_gxx| {
+            let mut song = pattern_song(&[
+                (0, 0, note(60, NoteCommand::None)),
+                (
+                    1,
+                    0,
+                    NoteEvent {
+                        sample_instr: 2,
+                        ..note(62, NoteCommand::SlideToNote(0x08))
+                    },
+                ),
+            ]);
+            song.compatible_gxx = compatible_gxx;
+            let (mut meta, sample) = sample();
+            meta.default_volume = 32;
+            song.samples[2] = Some((meta, sample));
+            let mut state = start(&song);
+            state.voices[0].as_mut().unwrap().set_volume(10);
+            next_tick(&mut state, &song);
+            let (meta, sample) = song.samples[1].clone().unwrap();
+            let first = SamplePlayer::new(sample, meta, SAMPLERATE, Note::default());
+            let voice = state.voices[0].as_ref().unwrap();
+            (voice.plays_same_sample(&first), voice.volume())
+        };
+        assert_eq!(play(true), (true, 32));
+        assert_eq!(play(false), (false, 32));
+    }
+
+    #[test]
+    fn retrigger_skips_the_note_tick() {
+        // Q1x lowers the volume by 1 on every retrigger
+        let song = command_song(NoteCommand::RetriggerNote(0x13), 8);
+        let volumes = per_tick(&song, 8, SamplePlayer::volume);
+        assert_eq!(volumes, [64, 64, 64, 63, 63, 63, 62, 62]);
+
+        let song = command_song(NoteCommand::RetriggerNote(0x11), 4);
+        assert_eq!(per_tick(&song, 4, SamplePlayer::volume), [64, 63, 62, 61]);
+    }
+
+    #[test]
+    fn tremor_mutes_after_on_ticks() {
+        let song = command_song(NoteCommand::Tremor(0x21), 6);
+        let audible = per_tick(&song, 6, |voice| voice.volume_factor() > 0.);
+        assert_eq!(audible, [true, true, false, true, true, false]);
+    }
+
+    #[test]
+    fn arpeggio_cycles_through_notes() {
+        let song = command_song(NoteCommand::Arpeggio(0x47), 6);
+        let modulation = per_tick(&song, 6, SamplePlayer::pitch_modulation);
+        assert_eq!(modulation, [0., 256., 448., 0., 256., 448.]);
+    }
+}
@@ -1,11 +1,14 @@
 use std::{num::NonZero, ops::ControlFlow};
 
 use crate::{
-    project::note_event::Note,
-    sample::{ProcessingFrame, ProcessingFunction, Sample, SampleMetaData},
+    project::{instrument::Instrument, note_event::Note},
+    sample::{LoopMode, ProcessingFrame, ProcessingFunction, Sample, SampleLoop, SampleMetaData},
 };
 
-use super::Frame;
+use super::{
+    instrument::{Filter, VoiceEnvelopes},
+    waveform, Frame,
+};
 
 #[repr(u8)]
 pub enum Interpolation {
@@ -44,12 +47,60 @@ pub struct SamplePlayer {
     meta: SampleMetaData,
 
     note: Note,
+    /// the note in the pattern. The note sample table of an instrument can play another note
+    pattern_note: Note,
+    /// note volume. ranges 0..=64
+    volume: u8,
+    /// global volume of the sample. ranges 0..=64
+    sample_volume: u8,
+    /// global volume of the instrument. ranges 0..=128
+    instrument_volume: u8,
+    /// changed by pitch slides and portamento. Relative to the note, in 1/64 semitones
+    pitch_offset: f32,
+    /// temporary pitch change by modulation effects. Doesn't change pitch_offset. in 1/64 semitones
+    pitch_modulation: f32,
+    /// temporary volume change by modulation effects. Doesn't change volume
+    volume_modulation: i8,
+    /// position in the waveform of the sample vibrato
+    auto_vibrato_position: u8,
+    /// the depth ramps up by the vibrato rate on each tick. The upper byte is the current depth.
+    auto_vibrato_depth: u16,
+    /// set by tremor for the current tick. Reset together with the modulation
+    muted: bool,
+    /// phase of the tremor. Keeps going over multiple rows
+    tremor_on: bool,
+    /// remaining ticks of the current tremor phase
+    tremor_ticks: u8,
+    /// ticks since the last retrigger
+    retrigger_ticks: u8,
     // position in the sample, the next output frame should be.
     // Done this way, so 0 is a valid, useful and intuitive value
     // always a valid position in the sample. checked against sample lenght on each change
     // stored as fixed point data: usize + f32
     // f32 ranges 0..1
     position: (usize, f32),
+    /// ping pong loops play backwards every other time
+    backwards: bool,
+    /// set by note off. Leaves the sustain loop
+    released: bool,
+    /// the sustain loop until the note is released, then the sample loop. Already clamped to the sample
+    active_loop: Option<SampleLoop>,
+    /// set by the instrument. Without one a fade cuts the note, like in IT
+    fadeout: u16,
+    /// goes down by fadeout on every tick once the note fades. The note ends at 0
+    fade_volume: u16,
+    fading: bool,
+    /// the instrument that plays the voice. None in sample mode
+    instrument: Option<u8>,
+    envelopes: VoiceEnvelopes,
+    /// value of the volume envelope on this tick. ranges 0..=1
+    envelope_volume: f32,
+    /// value of the pan envelope on this tick. ranges -32..=32
+    envelope_pan: f32,
+    /// same as IT: note off fades the note if the instrument has no volume envelope or the envelope loops
+    fade_on_release: bool,
+    /// set by the instrument
+    filter: Option<Filter>,
     // is_done: bool,
     out_rate: NonZero<u32>,
     // how much the position is advanced for each output sample.
@@ -58,20 +109,274 @@ pub struct SamplePlayer {
 }
 
 impl SamplePlayer {
+    pub const MAX_VOLUME: u8 = 64;
+    /// converts between a frequency and an IT amiga period. Amiga slides change this period.
+    const AMIGA_PERIOD_FACTOR: f32 = 1712. * 8363.;
+    pub const MAX_SAMPLE_VOLUME: u8 = 64;
+    pub const MAX_INSTRUMENT_VOLUME: u8 = 128;
+    pub const MAX_FADE_VOLUME: u16 = 1024;
+
     pub fn new(sample: Sample, meta: SampleMetaData, out_rate: NonZero<u32>, note: Note) -> Self {
-        let step_size = Self::compute_step_size(meta.sample_rate, out_rate, meta.base_note, note);
+        Self::with_offset(sample, meta, out_rate, note, 0)
+    }
+
+    /// starts playing offset frames into the sample. Offsets past the end are clamped to the end.
+    pub fn with_offset(
+        sample: Sample,
+        meta: SampleMetaData,
+        out_rate: NonZero<u32>,
+        note: Note,
+        offset: usize,
+    ) -> Self {
+        let step_size =
+            Self::compute_step_size(meta.sample_rate, out_rate, meta.base_note, note, 0.);
+        let position = Sample::PAD_SIZE_EACH + offset.min(sample.len());
+        let active_loop = Self::find_active_loop(&meta, sample.len(), false);
         Self {
             sample,
             meta,
-            position: (Sample::PAD_SIZE_EACH, 0.),
+            position: (position, 0.),
+            backwards: false,
+            released: false,
+            active_loop,
+            fadeout: 0,
+            fade_volume: Self::MAX_FADE_VOLUME,
+            fading: false,
+            instrument: None,
+            envelopes: VoiceEnvelopes::default(),
+            envelope_volume: 1.,
+            envelope_pan: 0.,
+            fade_on_release: false,
+            filter: None,
             out_rate,
             step_size,
             note,
+            pattern_note: note,
+            volume: meta.default_volume.min(Self::MAX_VOLUME),
+            sample_volume: meta.global_volume.min(Self::MAX_SAMPLE_VOLUME),
+            instrument_volume: Self::MAX_INSTRUMENT_VOLUME,
+            pitch_offset: 0.,
+            pitch_modulation: 0.,
+            volume_modulation: 0,
+            auto_vibrato_position: 0,
+            auto_vibrato_depth: 0,
+            muted: false,
+            tremor_on: false,
+            tremor_ticks: 0,
+            retrigger_ticks: 0,
+        }
+    }
+
+    pub fn volume(&self) -> u8 {
+        self.volume
+    }
+
+    /// position in the sample in frames
+    #[cfg(test)]
+    pub fn position(&self) -> usize {
+        self.position.0.saturating_sub(Sample::PAD_SIZE_EACH)
+    }
+
+    #[cfg(test)]
+    pub fn note(&self) -> Note {
+        self.note
+    }
+
+    pub fn pattern_note(&self) -> Note {
+        self.pattern_note
+    }
+
+    pub fn set_pattern_note(&mut self, note: Note) {
+        self.pattern_note = note;
+    }
+
+    #[cfg(test)]
+    pub fn is_released(&self) -> bool {
+        self.released
+    }
+
+    #[cfg(test)]
+    pub fn is_fading(&self) -> bool {
+        self.fading
+    }
+
+    pub fn plays_same_sample(&self, other: &Self) -> bool {
+        self.sample.ptr_eq(&other.sample)
+    }
+
+    /// clamps to MAX_VOLUME
+    pub fn set_volume(&mut self, volume: u8) {
+        self.volume = volume.min(Self::MAX_VOLUME);
+    }
+
+    /// clamps to 0..=MAX_VOLUME
+    pub fn slide_volume(&mut self, amount: i8) {
+        self.set_volume(self.volume.saturating_add_signed(amount));
+    }
+
+    /// note volume * sample volume * instrument volume * envelope volume, scaled to 0..=1.
+    /// Channel and song volumes are applied by the playback.
+    pub fn volume_factor(&self) -> f32 {
+        if self.muted {
+            return 0.;
         }
+        let volume = self
+            .volume
+            .saturating_add_signed(self.volume_modulation)
+            .min(Self::MAX_VOLUME);
+        f32::from(volume) / f32::from(Self::MAX_VOLUME)
+            * (f32::from(self.sample_volume) / f32::from(Self::MAX_SAMPLE_VOLUME))
+            * (f32::from(self.instrument_volume) / f32::from(Self::MAX_INSTRUMENT_VOLUME))
+            * (f32::from(self.fade_volume) / f32::from(Self::MAX_FADE_VOLUME))
+            * self.envelope_volume
+    }
+
+    /// Moves the pan of the channel by the pan envelope. Less so the closer the pan is to a side
+    pub fn pan(&self, pan: u8) -> u8 {
+        let range = 32. - (f32::from(pan) - 32.).abs();
+        (f32::from(pan) + self.envelope_pan * range / 32.).clamp(0., 64.) as u8
+    }
+
+    #[cfg(test)]
+    pub fn pitch_modulation(&self) -> f32 {
+        self.pitch_modulation
+    }
+
+    #[cfg(test)]
+    pub fn volume_modulation(&self) -> i8 {
+        self.volume_modulation
+    }
+
+    pub fn pitch_offset(&self) -> f32 {
+        self.pitch_offset
+    }
+
+    /// in 1/64 semitones
+    pub fn set_pitch_offset(&mut self, offset: f32) {
+        self.pitch_offset = offset;
+        self.set_step_size();
+    }
+
+    /// in 1/64 semitones
+    pub fn slide_pitch(&mut self, amount: f32) {
+        self.set_pitch_offset(self.pitch_offset + amount);
+    }
+
+    /// current playback frequency of the sample, without modulation
+    fn frequency(&self) -> f32 {
+        (f32::from(i16::from(self.note.get()) - i16::from(self.meta.base_note.get())) / 12.
+            + self.pitch_offset / 768.)
+            .exp2()
+            * self.meta.sample_rate.get() as f32
+    }
+
+    /// slides the amiga period instead of the pitch. Positive values lower the pitch.
+    pub fn slide_period(&mut self, amount: f32) {
+        let period = Self::AMIGA_PERIOD_FACTOR / self.frequency();
+        let new_frequency = Self::AMIGA_PERIOD_FACTOR / (period + amount).max(1.);
+        let ratio = new_frequency / self.frequency();
+        self.slide_pitch(ratio.log2() * 768.);
+    }
+
+    /// The updates every voice needs at the start of a tick, before the effects of the tick
+    pub fn tick(&mut self, instrument: Option<&Instrument>, random: &mut u32) {
+        self.reset_modulation();
+        self.update_fade();
+        self.auto_vibrato(random);
+        if let Some(instrument) = instrument {
+            self.update_envelopes(instrument);
+        }
+    }
+
+    /// removes all modulation. Active modulation effects add theirs again on every tick.
+    pub fn reset_modulation(&mut self) {
+        self.pitch_modulation = 0.;
+        self.volume_modulation = 0;
+        self.muted = false;
+        self.set_step_size();
+    }
+
+    /// in 1/64 semitones
+    pub fn add_pitch_modulation(&mut self, modulation: f32) {
+        self.pitch_modulation += modulation;
+        self.set_step_size();
+    }
+
+    pub fn add_volume_modulation(&mut self, modulation: i8) {
+        self.volume_modulation = self.volume_modulation.saturating_add(modulation);
+    }
+
+    /// sample vibrato as set in the metadata. Added on top of the pattern vibrato.
+    /// Needs to be called on every tick after the modulation was reset.
+    pub fn auto_vibrato(&mut self, random: &mut u32) {
+        if self.meta.vibrato_depth == 0 {
+            return;
+        }
+        let max_depth = u16::from(self.meta.vibrato_depth) << 8;
+        self.auto_vibrato_depth =
+            (self.auto_vibrato_depth + u16::from(self.meta.vibrato_rate)).min(max_depth);
+        let value = waveform(
+            self.meta.vibrato_waveform,
+            self.auto_vibrato_position,
+            random,
+        );
+        self.auto_vibrato_position = self
+            .auto_vibrato_position
+            .wrapping_add(self.meta.vibrato_speed);
+        // the depth is in 1/64 semitones, value ranges -64..=64
+        self.add_pitch_modulation(value * f32::from(self.auto_vibrato_depth >> 8) / 64.);
+    }
+
+    /// Needs to be called on every tick while tremor is active.
+    /// Switches between on and off after the given amount of ticks.
+    pub fn tremor(&mut self, on_ticks: u8, off_ticks: u8) {
+        if self.tremor_ticks == 0 {
+            self.tremor_on = !self.tremor_on;
+            self.tremor_ticks = if self.tremor_on { on_ticks } else { off_ticks };
+        }
+        self.tremor_ticks = self.tremor_ticks.saturating_sub(1);
+        self.muted |= !self.tremor_on;
+    }
+
+    /// Counts the ticks since the last retrigger. Returns true and restarts the count
+    /// when the interval is reached.
+    pub fn count_retrigger(&mut self, interval: u8) -> bool {
+        self.retrigger_ticks += 1;
+        if self.retrigger_ticks >= interval {
+            self.retrigger_ticks = 0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// plays the sample again from the start. Keeps volume and pitch
+    pub fn restart(&mut self) {
+        self.position = (Sample::PAD_SIZE_EACH, 0.);
+        self.backwards = false;
+    }
+
+    /// switches to another sample without restarting. The pitch follows the rate of the new sample.
+    pub fn change_sample(&mut self, sample: Sample, meta: SampleMetaData) {
+        self.sample = sample;
+        self.meta = meta;
+        self.active_loop = Self::find_active_loop(&meta, self.sample.len(), self.released);
+        self.sample_volume = meta.global_volume.min(Self::MAX_SAMPLE_VOLUME);
+        self.set_step_size();
+    }
+
+    /// Changes the note without changing the currently playing pitch. The pitch offset is changed
+    /// to the distance to the new note, so sliding the offset to zero reaches the new note.
+    pub fn retarget(&mut self, note: Note) {
+        self.pitch_offset += f32::from(i16::from(self.note.get()) - i16::from(note.get())) * 64.;
+        self.note = note;
     }
 
     pub fn check_position(&self) -> ControlFlow<()> {
-        if self.position.0 > self.sample.len_with_pad() - Sample::PAD_SIZE_EACH {
+        // a note that faded out completely is done as well
+        if self.fade_volume == 0
+            || self.position.0 > self.sample.len_with_pad() - Sample::PAD_SIZE_EACH
+        {
             ControlFlow::Break(())
         } else {
             ControlFlow::Continue(())
@@ -84,12 +389,17 @@ impl SamplePlayer {
         out_rate: NonZero<u32>,
         sample_base_note: Note,
         playing_note: Note,
+        // in 1/64 semitones
+        pitch_offset: f32,
     ) -> f32 {
         // original formula: (outrate / inrate) * (playing_freq / sample_base_freq).
         // Where each freq is computed with MIDI tuning standard formula: 440 * 2^((note - 69)/12)
         // manually reduced formula: 2^((play_note - sample_base_note)/12) * (outrate / inrate)
         // herbie (https://herbie.uwplse.org/demo/index.html) can't optimize further: https://herbie.uwplse.org/demo/e096ef89ee257ad611dd56378bd139a065a6bea0.02e7ec5a3709ad3e06968daa97db50d636f1e44b/graph.html
-        (f32::from(i16::from(playing_note.get()) - i16::from(sample_base_note.get())) / 12.).exp2()
+        // the offset is added to the semitones: 64 * 12 = 768 offset steps per octave
+        (f32::from(i16::from(playing_note.get()) - i16::from(sample_base_note.get())) / 12.
+            + pitch_offset / 768.)
+            .exp2()
             * (out_rate.get() as f32 / in_rate.get() as f32)
     }
 
@@ -99,6 +409,7 @@ impl SamplePlayer {
             self.out_rate,
             self.meta.base_note,
             self.note,
+            self.pitch_offset + self.pitch_modulation,
         );
     }
 
@@ -107,16 +418,196 @@ impl SamplePlayer {
         self.set_step_size();
     }
 
+    /// Plays the voice with the instrument. Doesn't apply the envelopes yet
+    pub fn set_instrument(&mut self, idx: u8, instrument: &Instrument) {
+        self.instrument = Some(idx);
+        self.instrument_volume = instrument.global_volume.min(Self::MAX_INSTRUMENT_VOLUME);
+        self.fadeout = instrument.fadeout;
+        self.fade_on_release = !instrument.volume_envelope.enabled
+            || instrument.volume_envelope.envelope_loop.is_some();
+        // same as IT: a fully open filter without resonance is skipped
+        let filter_envelope = instrument.filter_envelope && instrument.pitch_envelope.enabled;
+        let closed = instrument
+            .filter_cutoff
+            .is_some_and(|cutoff| cutoff < Filter::MAX_CUTOFF);
+        let resonant = instrument
+            .filter_resonance
+            .is_some_and(|resonance| resonance > 0);
+        self.filter = (filter_envelope || closed || resonant).then(Filter::default);
+    }
+
+    pub fn instrument(&self) -> Option<u8> {
+        self.instrument
+    }
+
+    pub(crate) fn envelopes(&self) -> VoiceEnvelopes {
+        self.envelopes
+    }
+
+    /// continues the envelopes of the previous voice that have the carry flag set
+    pub(crate) fn carry_envelopes(&mut self, previous: &Self, instrument: &Instrument) {
+        if previous.instrument == self.instrument {
+            self.envelopes.carry(previous.envelopes, instrument);
+        }
+    }
+
+    /// Applies the envelopes of the instrument for this tick and advances them.
+    /// Needs to be called on every tick after the modulation was reset
+    pub fn update_envelopes(&mut self, instrument: &Instrument) {
+        let volume = self.envelopes.volume.value(&instrument.volume_envelope);
+        self.envelope_volume = volume.map_or(1., |volume| volume / 64.);
+        if volume.is_some()
+            && self
+                .envelopes
+                .volume
+                .is_done(&instrument.volume_envelope, self.released)
+        {
+            // same as IT: the note fades at the end of the volume envelope and ends if it ends at 0
+            if volume == Some(0.) {
+                self.fade_volume = 0;
+            } else {
+                self.fading = true;
+            }
+        }
+
+        self.envelope_pan = self
+            .envelopes
+            .pan
+            .value(&instrument.pan_envelope)
+            .unwrap_or(0.);
+
+        let pitch = self.envelopes.pitch.value(&instrument.pitch_envelope);
+        let mut modifier = Filter::NO_MODIFIER;
+        if instrument.filter_envelope {
+            // -32..=32 to -256..=256
+            modifier = pitch.map_or(modifier, |pitch| pitch * 8.);
+        } else if let Some(pitch) = pitch {
+            // ranges -16..=16 semitones
+            self.add_pitch_modulation(pitch * 32.);
+        }
+        if let Some(filter) = &mut self.filter {
+            filter.set(
+                instrument.filter_cutoff.unwrap_or(Filter::MAX_CUTOFF),
+                instrument.filter_resonance.unwrap_or(0),
+                modifier,
+                self.out_rate,
+            );
+        }
+
+        self.envelopes
+            .volume
+            .advance(&instrument.volume_envelope, self.released);
+        self.envelopes
+            .pan
+            .advance(&instrument.pan_envelope, self.released);
+        self.envelopes
+            .pitch
+            .advance(&instrument.pitch_envelope, self.released);
+    }
+
+    /// Releases the note. The sample and the envelopes continue after their sustain loops
+    pub fn note_off(&mut self) {
+        self.released = true;
+        self.active_loop = Self::find_active_loop(&self.meta, self.sample.len(), true);
+        if self.fade_on_release {
+            self.fading = true;
+        }
+        // only a ping pong loop can take over the direction of the sustain loop
+        if self
+            .active_loop
+            .is_none_or(|sample_loop| sample_loop.mode == LoopMode::Forward)
+        {
+            self.backwards = false;
+        }
+    }
+
+    /// starts fading out the note. The fade advances with update_fade
+    pub fn note_fade(&mut self) {
+        self.fading = true;
+    }
+
+    /// Needs to be called on every tick
+    pub fn update_fade(&mut self) {
+        if self.fading {
+            self.fade_volume = if self.fadeout == 0 {
+                0
+            } else {
+                self.fade_volume.saturating_sub(self.fadeout)
+            };
+        }
+    }
+
+    /// The sustain loop until the note is released, then the sample loop.
+    /// Loops that aren't valid for the sample are skipped
+    fn find_active_loop(meta: &SampleMetaData, len: usize, released: bool) -> Option<SampleLoop> {
+        meta.sustain_loop
+            .filter(|_| !released)
+            .and_then(|sustain_loop| sustain_loop.clamped(len))
+            .or_else(|| {
+                meta.sample_loop
+                    .and_then(|sample_loop| sample_loop.clamped(len))
+            })
+    }
+
     /// steps self and sets is_done if needed
     fn step(&mut self) {
-        self.position.1 += self.step_size;
-        let floor = self.position.1.trunc();
-        self.position.1 -= floor;
-        self.position.0 += floor as usize;
+        if self.backwards {
+            self.position.1 -= self.step_size;
+            let floor = self.position.1.floor();
+            self.position.1 -= floor;
+            // floor is negative. going below the loop start is reflected afterwards
+            self.position.0 = self.position.0.saturating_sub(-floor as usize);
+        } else {
+            self.position.1 += self.step_size;
+            let floor = self.position.1.trunc();
+            self.position.1 -= floor;
+            self.position.0 += floor as usize;
+        }
+        if let Some(sample_loop) = self.active_loop {
+            self.apply_loop(sample_loop);
+        }
     }
 
-    pub fn iter<const INTERPOLATION: u8>(&mut self) -> SampleIter<'_, INTERPOLATION> {
-        SampleIter { inner: self }
+    /// keeps the position inside of the loop once the loop end was reached
+    fn apply_loop(&mut self, sample_loop: SampleLoop) {
+        let start = sample_loop.start + Sample::PAD_SIZE_EACH;
+        let end = sample_loop.end + Sample::PAD_SIZE_EACH;
+        match sample_loop.mode {
+            LoopMode::Forward => {
+                if self.position.0 >= end {
+                    self.position.0 = start + (self.position.0 - end) % (end - start);
+                }
+            }
+            LoopMode::PingPong => {
+                // the direction changes on the last frame of the loop and on the loop start
+                let last = end - 1;
+                let position = self.position.0 as f64 + f64::from(self.position.1);
+                let reflected = if !self.backwards && position > last as f64 {
+                    2. * last as f64 - position
+                } else if self.backwards && self.position.0 < start {
+                    2. * start as f64 - position
+                } else {
+                    return;
+                };
+                self.backwards = !self.backwards;
+                // a step longer than the loop would reflect outside of it
+                let reflected = reflected.clamp(start as f64, last as f64);
+                self.position = (reflected.trunc() as usize, reflected.fract() as f32);
+            }
+        }
+    }
+
+    /// index of the frame that follows idx when interpolating.
+    /// At the end of a forward loop this is the loop start, so the seam doesn't click
+    fn following_index(&self, idx: usize) -> usize {
+        match self.active_loop {
+            Some(SampleLoop {
+                start,
+                end,
+                mode: LoopMode::Forward,
+            }) if idx + 1 == end + Sample::PAD_SIZE_EACH => start + Sample::PAD_SIZE_EACH,
+            _ => idx + 1,
+        }
     }
 
     pub fn next<const INTERPOLATION: u8>(&mut self) -> Option<Frame> {
@@ -127,10 +618,13 @@ impl SamplePlayer {
             return None;
         }
 
-        let out = match interpolation {
+        let mut out = match interpolation {
             Interpolation::Nearest => self.compute_nearest(),
             Interpolation::Linear => self.compute_linear(),
         };
+        if let Some(filter) = &mut self.filter {
+            out = filter.process(out);
+        }
 
         self.step();
         Some(out)
@@ -147,6 +641,12 @@ impl SamplePlayer {
                 (diff * self.0) + data[0]
             }
         }
+        let next = self.following_index(self.position.0);
+        if next != self.position.0 + 1 {
+            // the frames at the loop seam aren't next to each other in the sample
+            let data = [self.sample.index(self.position.0), self.sample.index(next)];
+            return Linear(self.position.1).process(&data);
+        }
         self.sample
             .compute(self.position.0, Linear(self.position.1))
     }
@@ -155,21 +655,219 @@ impl SamplePlayer {
         let load_idx = if self.position.1 < 0.5 {
             self.position.0
         } else {
-            self.position.0 + 1
+            self.following_index(self.position.0)
         };
 
         self.sample.index(load_idx)
     }
 }
 
-pub struct SampleIter<'player, const INTERPOLATION: u8> {
-    inner: &'player mut SamplePlayer,
-}
+#[cfg(test)]
+mod test {
+    use std::num::NonZero;
+
+    use super::SamplePlayer;
+    use crate::{
+        project::note_event::Note,
+        sample::{LoopMode, Sample, SampleLoop, SampleMetaData},
+    };
+
+    const RATE: NonZero<u32> = NonZero::new(8363).unwrap();
+    const BASE_NOTE: u8 = 60;
+
+    /// at the base note every frame is output once. Lower notes step through the sample slower
+    fn player(data: &[f32], sample_loop: SampleLoop, note: u8) -> SamplePlayer {
+        sustain_player(data, Some(sample_loop), None, note)
+    }
+
+    fn sustain_player(
+        data: &[f32],
+        sample_loop: Option<SampleLoop>,
+        sustain_loop: Option<SampleLoop>,
+        note: u8,
+    ) -> SamplePlayer {
+        let meta = SampleMetaData {
+            default_volume: 64,
+            global_volume: 64,
+            default_pan: None,
+            vibrato_speed: 0,
+            vibrato_depth: 0,
+            vibrato_rate: 0,
+            vibrato_waveform: Default::default(),
+            sample_rate: RATE,
+            base_note: Note::new(BASE_NOTE).unwrap(),
+            sample_loop,
+            sustain_loop,
+        };
+        let sample = Sample::new_mono(data.iter().copied());
+        SamplePlayer::new(sample, meta, RATE, Note::new(note).unwrap())
+    }
+
+    /// the value of each frame is its index
+    fn ramp() -> Vec<f32> {
+        (0..10).map(|idx| idx as f32).collect()
+    }
+
+    fn play<const INTERPOLATION: u8>(player: &mut SamplePlayer, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .map(|_| player.next::<INTERPOLATION>().unwrap().sum_to_mono() / 2.)
+            .collect()
+    }
+
+    #[test]
+    fn forward_loop_wraps() {
+        let sample_loop = SampleLoop {
+            start: 2,
+            end: 6,
+            mode: LoopMode::Forward,
+        };
+        let mut player = player(&ramp(), sample_loop, BASE_NOTE);
+        assert_eq!(
+            play::<0>(&mut player, 12),
+            [0., 1., 2., 3., 4., 5., 2., 3., 4., 5., 2., 3.]
+        );
+    }
+
+    #[test]
+    fn ping_pong_loop_reflects() {
+        let sample_loop = SampleLoop {
+            start: 2,
+            end: 6,
+            mode: LoopMode::PingPong,
+        };
+        let mut player = player(&ramp(), sample_loop, BASE_NOTE);
+        assert_eq!(
+            play::<0>(&mut player, 14),
+            [0., 1., 2., 3., 4., 5., 4., 3., 2., 3., 4., 5., 4., 3.]
+        );
+    }
 
-impl<const INTERPOLATION: u8> Iterator for SampleIter<'_, INTERPOLATION> {
-    type Item = Frame;
+    #[test]
+    fn restart_plays_forward() {
+        let sample_loop = SampleLoop {
+            start: 2,
+            end: 6,
+            mode: LoopMode::PingPong,
+        };
+        let mut player = player(&ramp(), sample_loop, BASE_NOTE);
+        // on the way back through the loop
+        assert_eq!(play::<0>(&mut player, 7), [0., 1., 2., 3., 4., 5., 4.]);
+        player.restart();
+        assert_eq!(play::<0>(&mut player, 4), [0., 1., 2., 3.]);
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        self.inner.next::<INTERPOLATION>()
+    #[test]
+    fn sustain_loop_until_release() {
+        let sustain_loop = SampleLoop {
+            start: 2,
+            end: 4,
+            mode: LoopMode::Forward,
+        };
+        let sample_loop = SampleLoop {
+            start: 6,
+            end: 8,
+            mode: LoopMode::Forward,
+        };
+        let mut player = sustain_player(&ramp(), Some(sample_loop), Some(sustain_loop), BASE_NOTE);
+        assert_eq!(play::<0>(&mut player, 8), [0., 1., 2., 3., 2., 3., 2., 3.]);
+        player.note_off();
+        assert_eq!(play::<0>(&mut player, 8), [2., 3., 4., 5., 6., 7., 6., 7.]);
+
+        // without a sample loop the note plays to the end of the sample
+        let mut player = sustain_player(&ramp(), None, Some(sustain_loop), BASE_NOTE);
+        assert_eq!(play::<0>(&mut player, 6), [0., 1., 2., 3., 2., 3.]);
+        player.note_off();
+        assert_eq!(
+            play::<0>(&mut player, 9),
+            [2., 3., 4., 5., 6., 7., 8., 9., 0.]
+        );
+        assert!(player.check_position().is_break());
+    }
+
+    #[test]
+    fn release_keeps_ping_pong_direction() {
+        let sustain_loop = SampleLoop {
+            start: 2,
+            end: 6,
+            mode: LoopMode::PingPong,
+        };
+        let sample_loop = |mode| SampleLoop {
+            start: 0,
+            end: 8,
+            mode,
+        };
+        // released on the way back through the sustain loop
+        let mut player = sustain_player(
+            &ramp(),
+            Some(sample_loop(LoopMode::PingPong)),
+            Some(sustain_loop),
+            BASE_NOTE,
+        );
+        assert_eq!(play::<0>(&mut player, 7), [0., 1., 2., 3., 4., 5., 4.]);
+        player.note_off();
+        assert_eq!(play::<0>(&mut player, 6), [3., 2., 1., 0., 1., 2.]);
+
+        // a forward loop can only be played forwards
+        let mut player = sustain_player(
+            &ramp(),
+            Some(sample_loop(LoopMode::Forward)),
+            Some(sustain_loop),
+            BASE_NOTE,
+        );
+        assert_eq!(play::<0>(&mut player, 7), [0., 1., 2., 3., 4., 5., 4.]);
+        player.note_off();
+        assert_eq!(play::<0>(&mut player, 7), [3., 4., 5., 6., 7., 0., 1.]);
+    }
+
+    #[test]
+    fn fade_without_instrument_ends_on_next_tick() {
+        let sample_loop = SampleLoop {
+            start: 2,
+            end: 6,
+            mode: LoopMode::Forward,
+        };
+        let mut player = player(&ramp(), sample_loop, BASE_NOTE);
+        player.note_fade();
+        assert!(player.check_position().is_continue());
+        player.tick(None, &mut 0);
+        assert!(player.check_position().is_break());
+        assert_eq!(player.next::<0>(), None);
+    }
+
+    #[test]
+    fn loop_seam_interpolates_to_loop_start() {
+        let sample_loop = SampleLoop {
+            start: 2,
+            end: 6,
+            mode: LoopMode::Forward,
+        };
+        // an octave down, so every second frame lies between two sample frames
+        let mut player = player(&ramp(), sample_loop, BASE_NOTE - 12);
+        let out = play::<1>(&mut player, 14);
+        // between the last loop frame and the loop start, not frame 6
+        assert_eq!(out[10..], [5., 3.5, 2., 2.5]);
+    }
+
+    #[test]
+    fn looped_sine_has_no_click() {
+        const PERIOD: usize = 64;
+        // one period of a sine in the loop and full scale after it
+        let data: Vec<f32> = (0..PERIOD)
+            .map(|idx| (idx as f32 * std::f32::consts::TAU / PERIOD as f32).sin())
+            .chain(std::iter::repeat_n(1., PERIOD))
+            .collect();
+        let sample_loop = SampleLoop {
+            start: 0,
+            end: PERIOD,
+            mode: LoopMode::Forward,
+        };
+        // steps of about 0.7 frames cross the seam at different fractions
+        let mut player = player(&data, sample_loop, BASE_NOTE - 6);
+        let out = play::<1>(&mut player, 1000);
+        let max_step =
+            std::f32::consts::TAU / PERIOD as f32 * std::f32::consts::FRAC_1_SQRT_2 * 1.01;
+        for pair in out.windows(2) {
+            assert!((pair[1] - pair[0]).abs() <= max_step, "{pair:?}");
+        }
     }
 }
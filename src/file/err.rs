@@ -47,4 +47,8 @@ pub enum LoadDefect {
     OutOfBoundsValue,
     /// skips loading of the pointed to value
     OutOfBoundsPtr,
+    /// the sample uses an encoding that isn't supported. It isn't loaded
+    UnsupportedSample,
+    /// the compressed sample data is malformed. The rest of the sample is silent
+    CorruptSample,
 }
@@ -0,0 +1,203 @@
+// look at player/itcompress.c of schism tracker
+
+use std::io::Read;
+
+use crate::file::err::{LoadDefect, LoadErr};
+
+/// reads the bits of a block starting with the lowest bit of each byte
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl BitReader<'_> {
+    /// None if the block doesn't have enough bits left
+    fn read(&mut self, bits: u8) -> Option<u32> {
+        let mut value = 0;
+        for bit in 0..bits {
+            let byte = self.data.get(self.position / 8)?;
+            value |= u32::from((byte >> (self.position % 8)) & 1) << bit;
+            self.position += 1;
+        }
+        Some(value)
+    }
+}
+
+/// Decompresses one channel of an IT 2.14 or IT 2.15 compressed sample. The values range -1..1.
+///
+/// A malformed or truncated block is reported to the defect handler. The rest of the channel is silent then.
+pub fn decompress<R: Read, H: FnMut(LoadDefect)>(
+    reader: &mut R,
+    len: usize,
+    sixteen_bit: bool,
+    it215: bool,
+    defect_handler: &mut H,
+) -> Result<Vec<f32>, LoadErr> {
+    // each block decompresses to 0x8000 bytes
+    let (bits, block_len) = if sixteen_bit {
+        (16, 0x4000)
+    } else {
+        (8, 0x8000)
+    };
+    let mut out = Vec::with_capacity(len);
+    let mut block = Vec::new();
+    while out.len() < len {
+        let mut size = [0; 2];
+        let read = reader.read_exact(&mut size).and_then(|_| {
+            block.resize(usize::from(u16::from_le_bytes(size)), 0);
+            reader.read_exact(&mut block)
+        });
+        match read {
+            Ok(()) => (),
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
+                defect_handler(LoadDefect::CorruptSample);
+                break;
+            }
+            Err(err) => return Err(err.into()),
+        }
+        let count = block_len.min(len - out.len());
+        if decompress_block(&block, count, bits, it215, &mut out).is_none() {
+            defect_handler(LoadDefect::CorruptSample);
+            break;
+        }
+    }
+    out.resize(len, 0.);
+    Ok(out)
+}
+
+/// None if the block is malformed. The values decoded up to that point are kept
+fn decompress_block(
+    data: &[u8],
+    count: usize,
+    bits: u8,
+    it215: bool,
+    out: &mut Vec<f32>,
+) -> Option<()> {
+    let max_width = bits + 1;
+    // bits used to read a new width in method 1 and the range of widths in method 2
+    let (width_bits, border_offset) = if bits == 16 { (4, 8) } else { (3, 4) };
+    let scale = f32::from(1u16 << (bits - 1));
+    // wraps the value around like an integer with the sample bit width
+    let wrap = |value: i32| (value << (32 - bits)) >> (32 - bits);
+
+    let mut reader = BitReader { data, position: 0 };
+    let mut width = max_width;
+    let (mut d1, mut d2) = (0i32, 0i32);
+    let mut decoded = 0;
+    while decoded < count {
+        if width == 0 || width > max_width {
+            return None;
+        }
+        let value = reader.read(width)?;
+        if width < 7 {
+            // method 1: a single marker value, followed by the new width
+            if value == 1 << (width - 1) {
+                let new_width = reader.read(width_bits)? as u8 + 1;
+                width = if new_width < width {
+                    new_width
+                } else {
+                    new_width + 1
+                };
+                continue;
+            }
+        } else if width < max_width {
+            // method 2: the highest values of the width are new widths
+            let border = (((1u32 << bits) - 1) >> (max_width - width)) - border_offset;
+            if value > border && value <= border + 2 * border_offset {
+                let new_width = (value - border) as u8;
+                width = if new_width < width {
+                    new_width
+                } else {
+                    new_width + 1
+                };
+                continue;
+            }
+        } else if value & (1 << bits) != 0 {
+            // method 3: the highest bit is set on a new width
+            width = ((value + 1) & 0xFF) as u8;
+            continue;
+        }
+
+        // sign extend the value from its width
+        let shift = 32 - u32::from(width.min(bits));
+        let delta = ((value << shift) as i32) >> shift;
+        d1 = wrap(d1 + delta);
+        d2 = wrap(d2 + d1);
+        // IT 2.15 adds up the values twice
+        let sample = if it215 { d2 } else { d1 };
+        out.push(sample as f32 / scale);
+        decoded += 1;
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::decompress;
+
+    /// packs the (width, value) pairs into one block, starting with the lowest bit
+    fn block(fields: &[(u8, u32)]) -> Vec<u8> {
+        let mut data = vec![0, 0];
+        let mut bits = 0;
+        for &(width, value) in fields {
+            for bit in 0..width {
+                if bits % 8 == 0 {
+                    data.push(0);
+                }
+                *data.last_mut().unwrap() |= (((value >> bit) & 1) as u8) << (bits % 8);
+                bits += 1;
+            }
+        }
+        let len = (data.len() - 2) as u16;
+        data[..2].copy_from_slice(&len.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn decompress_8bit() {
+        // one block with the deltas 5 and 3, both 9 bit wide
+        let data = [3, 0, 0x05, 0x06, 0x00];
+
+        let out = decompress(&mut &data[..], 2, false, false, &mut |_| panic!()).unwrap();
+        assert_eq!(out, [5. / 128., 8. / 128.]);
+
+        let out = decompress(&mut &data[..], 2, false, true, &mut |_| panic!()).unwrap();
+        assert_eq!(out, [5. / 128., 13. / 128.]);
+    }
+
+    #[test]
+    fn malformed_block_is_silent() {
+        // changes the width to 0
+        let data = [2, 0, 0xFF, 0x01];
+        let mut defects = 0;
+        let out = decompress(&mut &data[..], 4, false, false, &mut |_| defects += 1).unwrap();
+        assert_eq!(out, [0.; 4]);
+        assert_eq!(defects, 1);
+    }
+
+    #[test]
+    fn decompress_16bit() {
+        let data = block(&[
+            (17, 0x4000),
+            // method 3 changes to width 9
+            (17, 0x1_0008),
+            (9, 100),
+            // method 2 changes to width 4, the border is 8 below the highest value
+            (9, 251),
+            (4, 3),
+            (4, 0xD),
+            // method 1 changes to width 13, the new width is 4 bit wide
+            (4, 8),
+            (4, 11),
+            (13, 1000),
+        ]);
+        let scale = |values: [i32; 5]| values.map(|value| value as f32 / 32768.);
+
+        let out = decompress(&mut &data[..], 5, true, false, &mut |_| panic!()).unwrap();
+        assert_eq!(out, scale([16384, 16484, 16487, 16484, 17484]));
+
+        // the second sum wraps around inside of 16 bit
+        let out = decompress(&mut &data[..], 5, true, true, &mut |_| panic!()).unwrap();
+        assert_eq!(out, scale([16384, -32668, -16181, 303, 17787]));
+    }
+}
@@ -64,6 +64,27 @@ pub struct ImpulseHeader {
 impl ImpulseHeader {
     pub(crate) const BASE_SIZE: usize = 0xC0; // = 192
 
+    /// the patterns select instruments instead of samples
+    pub fn uses_instruments(&self) -> bool {
+        (self.flags & 0x04) != 0
+    }
+
+    /// alternative is amiga slides
+    pub fn linear_slides(&self) -> bool {
+        (self.flags & 0x08) != 0
+    }
+
+    /// changes the behaviour of vibrato and Oxx
+    pub fn old_effects(&self) -> bool {
+        (self.flags & 0x10) != 0
+    }
+
+    /// Gxx gets its own memory instead of sharing it with Exx and Fxx and a sample on a Gxx row
+    /// doesn't change the sample of the voice
+    pub fn compatible_gxx(&self) -> bool {
+        (self.flags & 0x20) != 0
+    }
+
     /// Reader position needs to be at the beginning of the Header.
     ///
     /// Header is stored at the beginning of the File. length isn't constant, but at least 192 bytes
@@ -164,9 +185,9 @@ impl ImpulseHeader {
         };
 
         let instr_offsets = {
-            let mut data = vec![0; usize::from(instr_num)].into_boxed_slice();
+            let mut data = vec![0; usize::from(instr_num) * size_of::<u32>()].into_boxed_slice();
             reader.read_exact(&mut data)?;
-            data.chunks_exact(std::mem::size_of::<u32>())
+            data.chunks_exact(size_of::<u32>())
                 .map(|chunk| {
                     let value = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
                     if value <= Self::BASE_SIZE as u32 {
@@ -181,9 +202,9 @@ impl ImpulseHeader {
         };
 
         let sample_offsets = {
-            let mut data = vec![0; usize::from(sample_num)].into_boxed_slice();
+            let mut data = vec![0; usize::from(sample_num) * size_of::<u32>()].into_boxed_slice();
             reader.read_exact(&mut data)?;
-            data.chunks_exact(std::mem::size_of::<u32>())
+            data.chunks_exact(size_of::<u32>())
                 .map(|chunk| {
                     let value = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
                     if value <= Self::BASE_SIZE as u32 {
@@ -198,9 +219,9 @@ impl ImpulseHeader {
         };
 
         let pattern_offsets = {
-            let mut data = vec![0; usize::from(pattern_num)].into_boxed_slice();
+            let mut data = vec![0; usize::from(pattern_num) * size_of::<u32>()].into_boxed_slice();
             reader.read_exact(&mut data)?;
-            data.chunks_exact(std::mem::size_of::<u32>())
+            data.chunks_exact(size_of::<u32>())
                 .map(|chunk| {
                     let value = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
                     if value == 0 {
@@ -1,9 +1,12 @@
 use std::array;
+use std::ops::RangeInclusive;
 
 use crate::file::err;
 use crate::file::err::LoadDefect;
+use crate::project::instrument::{Envelope, EnvelopeLoop, EnvelopeNode, Instrument};
+use crate::project::note_event::Note;
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum NewNoteAction {
     #[default]
     Cut = 0,
@@ -26,7 +29,7 @@ impl TryFrom<u8> for NewNoteAction {
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum DuplicateCheckType {
     #[default]
     Off = 0,
@@ -49,7 +52,7 @@ impl TryFrom<u8> for DuplicateCheckType {
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum DuplicateCheckAction {
     #[default]
     Cut = 0,
@@ -98,7 +101,7 @@ pub struct ImpulseInstrument {
 }
 
 impl ImpulseInstrument {
-    const SIZE: usize = 554;
+    pub const SIZE: usize = 554;
 
     pub fn parse<H: FnMut(LoadDefect)>(
         buf: &[u8; Self::SIZE],
@@ -111,7 +114,7 @@ impl ImpulseInstrument {
         // unwrap is okay as the slice length is const
         let dos_file_name: [u8; 12] = buf[0x04..=0x0F].try_into().unwrap();
 
-        if buf[10] != 0 {
+        if buf[0x10] != 0 {
             return Err(err::LoadErr::Invalid);
         }
         let new_note_action = match NewNoteAction::try_from(buf[0x11]) {
@@ -158,7 +161,8 @@ impl ImpulseInstrument {
             64
         };
 
-        let default_pan = if buf[0x19] == 128 {
+        // bit 7 turns the pan off
+        let default_pan = if buf[0x19] & 0x80 != 0 {
             None
         } else if buf[0x19] > 64 {
             defect_handler(LoadDefect::OutOfBoundsValue);
@@ -167,48 +171,68 @@ impl ImpulseInstrument {
             Some(buf[0x19])
         };
 
-        let random_volume = buf[0x1A];
-        assert!(random_volume <= 100);
-        let random_pan = buf[0x1B];
-        assert!(random_pan <= 100);
+        let random_volume = if buf[0x1A] <= 100 {
+            buf[0x1A]
+        } else {
+            defect_handler(LoadDefect::OutOfBoundsValue);
+            0
+        };
+        let random_pan = if buf[0x1B] <= 100 {
+            buf[0x1B]
+        } else {
+            defect_handler(LoadDefect::OutOfBoundsValue);
+            0
+        };
         let created_with = u16::from_le_bytes([buf[0x1C], buf[0x1D]]);
         let number_of_samples = buf[0x1E];
 
-        let name = String::from_utf8(
+        let name = match String::from_utf8(
             buf[0x20..=0x39]
                 .split(|b| *b == 0)
                 .next()
                 .unwrap()
                 .to_owned(),
-        )
-        .unwrap();
+        ) {
+            Ok(name) => name,
+            Err(_) => {
+                defect_handler(LoadDefect::InvalidText);
+                String::new()
+            }
+        };
 
         let initial_filter_cutoff = buf[0x3A];
         let initial_filter_resonance = buf[0x3B];
         let midi_channel = buf[0x3C];
         let midi_program = buf[0x3D];
         let midi_bank = u16::from_le_bytes([buf[0x3E], buf[0x3F]]);
-        let note_sample_table: [(u8, u8); 120] = buf[0x030..0x130]
-            .chunks_exact(2)
-            .map(|chunk| (chunk[0], chunk[1]))
-            .collect::<Vec<(u8, u8)>>()
-            .try_into()
-            .unwrap();
+        let note_sample_table: [(u8, u8); 120] = array::from_fn(|idx| {
+            let note = buf[0x40 + idx * 2];
+            let sample = buf[0x41 + idx * 2];
+            if note <= 119 {
+                (note, sample)
+            } else {
+                defect_handler(LoadDefect::OutOfBoundsValue);
+                (idx as u8, sample)
+            }
+        });
 
         let volume_envelope = ImpulseEnvelope::load(
             &buf[0x130..0x130 + ImpulseEnvelope::SIZE]
                 .try_into()
                 .unwrap(),
+            defect_handler,
         );
         let pan_envelope = ImpulseEnvelope::load(
             &buf[0x182..0x182 + ImpulseEnvelope::SIZE]
                 .try_into()
                 .unwrap(),
+            defect_handler,
         );
         let pitch_envelope = ImpulseEnvelope::load(
             &buf[0x1D4..0x1D4 + ImpulseEnvelope::SIZE]
                 .try_into()
                 .unwrap(),
+            defect_handler,
         );
 
         Ok(Self {
@@ -237,6 +261,31 @@ impl ImpulseInstrument {
             pitch_envelope,
         })
     }
+
+    pub fn instrument(&self) -> Instrument {
+        Instrument {
+            note_sample_table: self
+                .note_sample_table
+                .map(|(note, sample)| (Note::new(note).unwrap_or_default(), sample)),
+            global_volume: self.global_volume,
+            default_pan: self.default_pan,
+            new_note_action: self.new_note_action,
+            duplicate_check_type: self.duplicate_check_type,
+            duplicate_check_action: self.duplicate_check_action,
+            fadeout: self.fade_out,
+            random_volume: self.random_volume,
+            random_pan: self.random_pan,
+            volume_envelope: self.volume_envelope.envelope(0..=64),
+            pan_envelope: self.pan_envelope.envelope(-32..=32),
+            pitch_envelope: self.pitch_envelope.envelope(-32..=32),
+            filter_envelope: self.pitch_envelope.flags & 0x80 != 0,
+            // bit 7 turns them on
+            filter_cutoff: (self.initial_filter_cutoff & 0x80 != 0)
+                .then_some(self.initial_filter_cutoff & 0x7F),
+            filter_resonance: (self.initial_filter_resonance & 0x80 != 0)
+                .then_some(self.initial_filter_resonance & 0x7F),
+        }
+    }
 }
 
 /// flags and node values are interpreted differently depending on the type of envelope.
@@ -254,10 +303,16 @@ pub struct ImpulseEnvelope {
 
 impl ImpulseEnvelope {
     const SIZE: usize = 81; // = 0x51
+    const MAX_NODES: u8 = 25;
 
-    fn load(buf: &[u8; Self::SIZE]) -> Self {
+    fn load<H: FnMut(LoadDefect)>(buf: &[u8; Self::SIZE], defect_handler: &mut H) -> Self {
         let flags = buf[0];
-        let num_node_points = buf[1];
+        let num_node_points = if buf[1] <= Self::MAX_NODES {
+            buf[1]
+        } else {
+            defect_handler(LoadDefect::OutOfBoundsValue);
+            Self::MAX_NODES
+        };
         let loop_start = buf[2];
         let loop_end = buf[3];
         let sustain_loop_start = buf[4];
@@ -281,4 +336,72 @@ impl ImpulseEnvelope {
             nodes,
         }
     }
+
+    /// Node values are clamped to the range. Loops that don't fit the nodes are removed
+    fn envelope(&self, values: RangeInclusive<i8>) -> Envelope {
+        let len = usize::from(self.num_node_points);
+        let env_loop = |enabled: bool, start: u8, end: u8| {
+            (enabled && start <= end && usize::from(end) < len)
+                .then_some(EnvelopeLoop { start, end })
+        };
+
+        Envelope {
+            enabled: self.flags & 0x01 != 0,
+            nodes: self.nodes[..len]
+                .iter()
+                .map(|(value, tick)| EnvelopeNode {
+                    tick: *tick,
+                    value: (*value as i8).clamp(*values.start(), *values.end()),
+                })
+                .collect(),
+            envelope_loop: env_loop(self.flags & 0x02 != 0, self.loop_start, self.loop_end),
+            sustain_loop: env_loop(
+                self.flags & 0x04 != 0,
+                self.sustain_loop_start,
+                self.sustain_loop_end,
+            ),
+            carry: self.flags & 0x08 != 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ImpulseInstrument;
+
+    #[test]
+    fn parse_note_sample_table_and_envelope() {
+        let mut buf = [0; ImpulseInstrument::SIZE];
+        buf[..4].copy_from_slice(b"IMPI");
+        // a dos file name that uses all of its bytes
+        buf[0x04..0x10].copy_from_slice(b"SNARE001.ITI");
+        // C-5 plays D-5 of sample 3
+        buf[0x40 + 60 * 2] = 62;
+        buf[0x41 + 60 * 2] = 3;
+        // volume envelope with two nodes and a sustain loop on the second one
+        buf[0x130] = 0x01 | 0x04;
+        buf[0x131] = 2;
+        buf[0x134] = 1;
+        buf[0x135] = 1;
+        buf[0x136] = 64;
+        buf[0x139] = 32;
+        buf[0x13A] = 10;
+
+        let instrument = ImpulseInstrument::parse(&buf, &mut |_| panic!())
+            .unwrap()
+            .instrument();
+        let (note, sample) = instrument.note_sample_table[60];
+        assert_eq!((note.get(), sample), (62, 3));
+
+        let envelope = instrument.volume_envelope;
+        assert!(envelope.enabled);
+        assert_eq!(envelope.nodes.len(), 2);
+        assert_eq!((envelope.nodes[0].tick, envelope.nodes[0].value), (0, 64));
+        assert_eq!((envelope.nodes[1].tick, envelope.nodes[1].value), (10, 32));
+        assert_eq!(envelope.envelope_loop, None);
+        assert_eq!(
+            envelope.sustain_loop.map(|l| (l.start, l.end)),
+            Some((1, 1))
+        );
+    }
 }
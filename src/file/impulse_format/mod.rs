@@ -1,3 +1,4 @@
+pub mod compression;
 pub mod header;
 pub mod instrument;
 pub mod pattern;
@@ -1,7 +1,7 @@
 use crate::file::err;
 use crate::file::err::LoadDefect;
 use crate::project::event_command::NoteCommand;
-use crate::project::note_event::{Note, NoteEvent, VolumeEffect};
+use crate::project::note_event::{NoteAction, NoteEvent, VolumeEffect};
 use crate::project::pattern::{InPatternPosition, Pattern};
 
 /// reader should be buffered in some way and not do a syscall on every read call.
@@ -67,11 +67,11 @@ pub fn parse_pattern<R: std::io::Read + std::io::Seek, H: FnMut(LoadDefect)>(
         // Note
         if (maskvar & 0b00000001) != 0 {
             reader.read_exact(&mut scratch)?;
-            let note = match Note::new(scratch[0]) {
+            let note = match NoteAction::try_from(scratch[0]) {
                 Ok(n) => n,
                 Err(_) => {
                     defect_handler(LoadDefect::OutOfBoundsValue);
-                    Note::default()
+                    NoteAction::None
                 }
             };
 
@@ -1,13 +1,20 @@
 // look at player/csndfile.c csf_read_sample
 
-use std::num::NonZeroU32;
+use std::{
+    io::{Read, Seek},
+    num::{NonZero, NonZeroU32},
+};
 
-use crate::file::{
-    err::{LoadDefect, LoadErr},
-    InFilePtr,
+use crate::{
+    file::{
+        err::{LoadDefect, LoadErr},
+        InFilePtr,
+    },
+    project::note_event::Note,
+    sample::{LoopMode, Sample, SampleLoop, SampleMetaData},
 };
 
-use super::header;
+use super::{compression, header};
 
 #[derive(Debug, Default, Clone, Copy)]
 pub enum VibratoWave {
@@ -163,7 +170,8 @@ pub struct ImpulseSampleHeader {
     pub c5_speed: u32,
     pub sustain_start: u32,
     pub sustain_end: u32,
-    pub data_ptr: InFilePtr,
+    /// None if the sample has no data
+    pub data_ptr: Option<InFilePtr>,
     pub vibrato_speed: u8,
     pub vibrato_depth: u8,
     pub vibrato_type: VibratoWave,
@@ -171,7 +179,9 @@ pub struct ImpulseSampleHeader {
 }
 
 impl ImpulseSampleHeader {
-    const SIZE: usize = 80;
+    pub const SIZE: usize = 80;
+    /// used when the file doesn't specify a valid speed
+    const DEFAULT_C5_SPEED: u32 = 8363;
 
     pub fn parse<H: FnMut(LoadDefect)>(
         buf: &[u8; Self::SIZE],
@@ -220,6 +230,9 @@ impl ImpulseSampleHeader {
                 defect_handler(LoadDefect::OutOfBoundsValue);
                 // no idea what is a good default here
                 9999999 / 2
+            } else if speed == 0 {
+                defect_handler(LoadDefect::OutOfBoundsValue);
+                Self::DEFAULT_C5_SPEED
             } else {
                 speed
             }
@@ -231,10 +244,15 @@ impl ImpulseSampleHeader {
 
         let data_ptr = {
             let value = u32::from_le_bytes([buf[0x48], buf[0x49], buf[0x4A], buf[0x4B]]);
-            if value < header::ImpulseHeader::BASE_SIZE as u32 {
-                return Err(LoadErr::Invalid);
+            if !flags.has_sample() {
+                None
+            } else if value < header::ImpulseHeader::BASE_SIZE as u32 {
+                defect_handler(LoadDefect::OutOfBoundsPtr);
+                None
+            } else {
+                // larger than BASE_SIZE, so not 0
+                Some(InFilePtr(NonZeroU32::new(value).unwrap()))
             }
-            InFilePtr(NonZeroU32::new(value).unwrap())
         };
 
         let vibrato_speed = if buf[0x4C] > 64 {
@@ -288,3 +306,261 @@ impl ImpulseSampleHeader {
         })
     }
 }
+
+impl ImpulseSampleHeader {
+    /// the metadata needed to play the sample
+    pub fn meta_data(&self) -> SampleMetaData {
+        fn sample_loop(start: u32, end: u32, ping_pong: bool) -> SampleLoop {
+            SampleLoop {
+                start: start as usize,
+                end: end as usize,
+                mode: if ping_pong {
+                    LoopMode::PingPong
+                } else {
+                    LoopMode::Forward
+                },
+            }
+        }
+
+        SampleMetaData {
+            default_volume: self.default_volume.min(64),
+            global_volume: self.global_volume,
+            // bit 7 has to be set for the pan to be used
+            default_pan: (self.default_pan & 0x80 != 0)
+                .then_some((self.default_pan & 0x7F).min(64)),
+            vibrato_speed: self.vibrato_speed,
+            vibrato_depth: self.vibrato_depth,
+            vibrato_rate: self.vibrato_rate,
+            vibrato_waveform: self.vibrato_type,
+            sample_rate: NonZero::new(self.c5_speed)
+                .unwrap_or(const { NonZero::new(Self::DEFAULT_C5_SPEED).unwrap() }),
+            // the c5 speed is the rate at which the sample plays C-5
+            base_note: Note::default(),
+            sample_loop: self
+                .flags
+                .uses_loop()
+                .then(|| sample_loop(self.loop_start, self.loop_end, self.flags.ping_pong_loop())),
+            sustain_loop: self.flags.uses_sustain_loop().then(|| {
+                sample_loop(
+                    self.sustain_start,
+                    self.sustain_end,
+                    self.flags.ping_pong_sustain_loop(),
+                )
+            }),
+        }
+    }
+
+    /// Reads and decodes the sample data. None if the sample has no data or can't be loaded.
+    pub fn load_data<R: Read + Seek, H: FnMut(LoadDefect)>(
+        &self,
+        reader: &mut R,
+        defect_handler: &mut H,
+    ) -> Result<Option<Sample>, LoadErr> {
+        let Some(data_ptr) = self.data_ptr else {
+            return Ok(None);
+        };
+        let length = self.length as usize;
+        if length == 0 {
+            return Ok(None);
+        }
+        if length > Sample::MAX_LENGTH {
+            defect_handler(LoadDefect::OutOfBoundsValue);
+            return Ok(None);
+        }
+        if self.convert.byte_delta() || self.convert.tx_wave_12bit() {
+            defect_handler(LoadDefect::UnsupportedSample);
+            return Ok(None);
+        }
+
+        let channels = if self.flags.is_steroe() { 2 } else { 1 };
+        data_ptr.move_to_self(reader)?;
+
+        if self.flags.is_compressed() {
+            // the channels are compressed one after the other. IT 2.15 is marked as delta
+            let mut decompress = || {
+                compression::decompress(
+                    reader,
+                    length,
+                    self.flags.is_16bit(),
+                    self.convert.delta_samples(),
+                    defect_handler,
+                )
+            };
+            let left = decompress()?;
+            let sample = if channels == 1 {
+                Sample::new_mono(left)
+            } else {
+                let right = decompress()?;
+                Sample::new_stereo_interpolated(
+                    left.into_iter().zip(right).flat_map(|(l, r)| [l, r]),
+                )
+            };
+            return Ok(Some(sample));
+        }
+
+        let bytes_per_frame = if self.flags.is_16bit() { 2 } else { 1 };
+        let mut data = vec![0; length * bytes_per_frame * channels];
+        match reader.read_exact(&mut data) {
+            Ok(()) => (),
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
+                defect_handler(LoadDefect::OutOfBoundsPtr);
+                return Ok(None);
+            }
+            Err(err) => return Err(err.into()),
+        }
+
+        let sample = if channels == 1 {
+            Sample::new_mono(self.decode_channel(&data))
+        } else {
+            // the channels are stored one after the other
+            let (left, right) = data.split_at(data.len() / 2);
+            let left = self.decode_channel(left);
+            let right = self.decode_channel(right);
+            Sample::new_stereo_interpolated(left.zip(right).flat_map(|(l, r)| [l, r]))
+        };
+        Ok(Some(sample))
+    }
+
+    /// converts the data of one channel to values in -1..1
+    fn decode_channel<'a>(&self, data: &'a [u8]) -> impl Iterator<Item = f32> + 'a {
+        let convert = self.convert;
+        let sixteen_bit = self.flags.is_16bit();
+        let bytes = if sixteen_bit { 2 } else { 1 };
+        // delta values are added up per channel
+        let mut last = 0u16;
+        data.chunks_exact(bytes).map(move |chunk| {
+            let raw = match (sixteen_bit, convert.is_big_endian()) {
+                (false, _) => u16::from(chunk[0]),
+                (true, false) => u16::from_le_bytes([chunk[0], chunk[1]]),
+                (true, true) => u16::from_be_bytes([chunk[0], chunk[1]]),
+            };
+            let mut value = if convert.delta_samples() {
+                last = last.wrapping_add(raw);
+                last
+            } else {
+                raw
+            };
+            if sixteen_bit {
+                if !convert.is_signed() {
+                    value ^= 0x8000;
+                }
+                f32::from(value as i16) / 32768.
+            } else {
+                // keep delta values inside of 8 bit
+                value &= 0xFF;
+                if !convert.is_signed() {
+                    value ^= 0x80;
+                }
+                f32::from(value as u8 as i8) / 128.
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::ImpulseSampleHeader;
+    use crate::{
+        audio_processing::Frame, file::impulse_format::header::ImpulseHeader, sample::Sample,
+    };
+
+    /// the data of the sample is right after the header of the file
+    fn header(flags: u8, convert: u8, length: u32) -> ImpulseSampleHeader {
+        let mut buf = [0; ImpulseSampleHeader::SIZE];
+        buf[..4].copy_from_slice(b"IMPS");
+        buf[0x12] = flags;
+        buf[0x2E] = convert;
+        buf[0x30..0x34].copy_from_slice(&length.to_le_bytes());
+        buf[0x3C..0x40].copy_from_slice(&8363u32.to_le_bytes());
+        buf[0x48..0x4C].copy_from_slice(&(ImpulseHeader::BASE_SIZE as u32).to_le_bytes());
+        ImpulseSampleHeader::parse(&buf, &mut |_| panic!()).unwrap()
+    }
+
+    fn decode(header: &ImpulseSampleHeader, data: &[u8]) -> Vec<f32> {
+        header.decode_channel(data).collect()
+    }
+
+    #[test]
+    fn decode_8bit() {
+        let unsigned = header(0x01, 0x00, 3);
+        assert_eq!(
+            decode(&unsigned, &[0x80, 0xFF, 0x00]),
+            [0., 127. / 128., -1.]
+        );
+
+        let signed = header(0x01, 0x01, 3);
+        assert_eq!(decode(&signed, &[0x40, 0xC0, 0x80]), [0.5, -0.5, -1.]);
+
+        // the sum wraps around inside of 8 bit
+        let delta = header(0x01, 0x05, 3);
+        assert_eq!(
+            decode(&delta, &[0x7F, 0x01, 0xFF]),
+            [127. / 128., -1., 127. / 128.]
+        );
+    }
+
+    #[test]
+    fn decode_16bit() {
+        let signed = header(0x03, 0x01, 3);
+        assert_eq!(
+            decode(&signed, &[0x00, 0x40, 0x00, 0xC0, 0xFF, 0x7F]),
+            [0.5, -0.5, 32767. / 32768.]
+        );
+
+        let unsigned = header(0x03, 0x00, 2);
+        assert_eq!(decode(&unsigned, &[0x00, 0x80, 0x00, 0x00]), [0., -1.]);
+
+        let big_endian = header(0x03, 0x03, 1);
+        assert_eq!(decode(&big_endian, &[0x40, 0x00]), [0.5]);
+
+        let delta = header(0x03, 0x05, 2);
+        assert_eq!(decode(&delta, &[0x00, 0x40, 0x00, 0x40]), [0.5, -1.]);
+    }
+
+    #[test]
+    fn load_split_stereo() {
+        // all left frames, then all right frames
+        let header = header(0x01 | 0x04, 0x01, 2);
+        let mut file = vec![0; ImpulseHeader::BASE_SIZE];
+        file.extend([0x40, 0x20, 0xC0, 0x00]);
+
+        let sample = header
+            .load_data(&mut Cursor::new(file), &mut |_| panic!())
+            .unwrap()
+            .unwrap();
+        assert_eq!(sample.len(), 2);
+        assert_eq!(
+            sample.index(Sample::PAD_SIZE_EACH),
+            Frame::from([0.5, -0.5])
+        );
+        assert_eq!(
+            sample.index(Sample::PAD_SIZE_EACH + 1),
+            Frame::from([0.25, 0.])
+        );
+    }
+
+    #[test]
+    fn load_compressed_stereo() {
+        // one block per channel, with the 9 bit deltas 5, 3 on the left and 1, 2 on the right
+        let header = header(0x01 | 0x04 | 0x08, 0x01, 2);
+        let mut file = vec![0; ImpulseHeader::BASE_SIZE];
+        file.extend([3, 0, 0x05, 0x06, 0x00]);
+        file.extend([3, 0, 0x01, 0x04, 0x00]);
+
+        let sample = header
+            .load_data(&mut Cursor::new(file), &mut |_| panic!())
+            .unwrap()
+            .unwrap();
+        assert_eq!(sample.len(), 2);
+        assert_eq!(
+            sample.index(Sample::PAD_SIZE_EACH),
+            Frame::from([5. / 128., 1. / 128.])
+        );
+        assert_eq!(
+            sample.index(Sample::PAD_SIZE_EACH + 1),
+            Frame::from([8. / 128., 3. / 128.])
+        );
+    }
+}
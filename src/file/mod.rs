@@ -1,5 +1,5 @@
 use err::LoadErr;
-use impulse_format::{header, pattern};
+use impulse_format::{header, instrument::ImpulseInstrument, pattern, sample::ImpulseSampleHeader};
 
 use crate::project::song::Song;
 
@@ -41,5 +41,132 @@ pub fn parse_song<R: std::io::Read + std::io::Seek>(reader: &mut R) -> Result<So
         song.patterns[idx] = pattern;
     }
 
+    // parse samples. IT numbers them from 1, as 0 means no sample in a pattern
+    for (idx, ptr) in header
+        .sample_offsets
+        .iter()
+        .enumerate()
+        .flat_map(|(idx, ptr)| ptr.map(|ptr| (idx + 1, ptr)))
+        .take_while(|(idx, _)| *idx < Song::MAX_SAMPLES_INSTR)
+    {
+        ptr.move_to_self(reader)?;
+        let mut buf = [0; ImpulseSampleHeader::SIZE];
+        reader.read_exact(&mut buf)?;
+        let sample_header = ImpulseSampleHeader::parse(&buf, &mut defect_handler)?;
+        if let Some(sample) = sample_header.load_data(reader, &mut defect_handler)? {
+            song.samples[idx] = Some((sample_header.meta_data(), sample));
+        }
+    }
+
+    // parse instruments. Numbered from 1 like the samples
+    for (idx, ptr) in header
+        .instr_offsets
+        .iter()
+        .enumerate()
+        .flat_map(|(idx, ptr)| ptr.map(|ptr| (idx + 1, ptr)))
+        .take_while(|(idx, _)| *idx < Song::MAX_SAMPLES_INSTR)
+    {
+        ptr.move_to_self(reader)?;
+        let mut buf = [0; ImpulseInstrument::SIZE];
+        reader.read_exact(&mut buf)?;
+        let instrument = ImpulseInstrument::parse(&buf, &mut defect_handler)?;
+        song.instruments[idx] = Some(instrument.instrument());
+    }
+
     Ok(song)
 }
+
+#[cfg(test)]
+mod test {
+    use std::{io::Cursor, num::NonZero};
+
+    use super::parse_song;
+    use crate::{
+        audio_processing::playback::PlaybackState,
+        file::impulse_format::{header::ImpulseHeader, sample::ImpulseSampleHeader},
+        manager::PlaybackSettings,
+        project::{
+            note_event::{Note, NoteAction, NoteEvent},
+            pattern::InPatternPosition,
+        },
+        sample::{LoopMode, SampleLoop},
+    };
+
+    const SAMPLE_LENGTH: usize = 16;
+
+    /// one order and a looped 8 bit square wave as sample 1
+    fn file() -> Vec<u8> {
+        const SAMPLE_HEADER: usize = ImpulseHeader::BASE_SIZE + 2 + 4;
+        const SAMPLE_DATA: usize = SAMPLE_HEADER + ImpulseSampleHeader::SIZE;
+
+        let mut file = vec![0; ImpulseHeader::BASE_SIZE];
+        file[..4].copy_from_slice(b"IMPM");
+        // order and sample count
+        file[0x20] = 2;
+        file[0x24] = 1;
+        // global volume, mix volume, speed and tempo
+        file[0x30..0x34].copy_from_slice(&[128, 48, 6, 125]);
+        file[0x40..0x80].fill(32);
+        file[0x80..0xC0].fill(64);
+        // the order list, then the offset of the sample header
+        file.extend([0, 255]);
+        file.extend((SAMPLE_HEADER as u32).to_le_bytes());
+
+        let mut sample = [0; ImpulseSampleHeader::SIZE];
+        sample[..4].copy_from_slice(b"IMPS");
+        sample[0x11] = 64;
+        // has data and a loop
+        sample[0x12] = 0x01 | 0x10;
+        sample[0x13] = 64;
+        // signed
+        sample[0x2E] = 0x01;
+        sample[0x30..0x34].copy_from_slice(&(SAMPLE_LENGTH as u32).to_le_bytes());
+        sample[0x38..0x3C].copy_from_slice(&(SAMPLE_LENGTH as u32).to_le_bytes());
+        sample[0x3C..0x40].copy_from_slice(&22050u32.to_le_bytes());
+        sample[0x48..0x4C].copy_from_slice(&(SAMPLE_DATA as u32).to_le_bytes());
+        file.extend(sample);
+
+        file.extend(
+            (0..SAMPLE_LENGTH).map(|idx| if idx < SAMPLE_LENGTH / 2 { 0x40 } else { 0xC0 }),
+        );
+        file
+    }
+
+    #[test]
+    fn parse_song_loads_samples() {
+        let mut song = parse_song(&mut Cursor::new(file())).unwrap();
+
+        let (meta, sample) = song.samples[1].as_ref().unwrap();
+        assert_eq!(sample.len(), SAMPLE_LENGTH);
+        assert_eq!(meta.sample_rate.get(), 22050);
+        assert_eq!(
+            meta.sample_loop,
+            Some(SampleLoop {
+                start: 0,
+                end: SAMPLE_LENGTH,
+                mode: LoopMode::Forward
+            })
+        );
+        assert!(song.samples[2].is_none());
+
+        // the loaded sample is audible
+        song.patterns[0].set_event(
+            InPatternPosition { row: 0, channel: 0 },
+            NoteEvent {
+                note: NoteAction::Play(Note::default()),
+                sample_instr: 1,
+                ..Default::default()
+            },
+        );
+        let settings = PlaybackSettings::Order {
+            idx: 0,
+            should_loop: false,
+        };
+        let mut playback =
+            PlaybackState::new(&song, NonZero::new(44100).unwrap(), settings).unwrap();
+        assert!(playback
+            .iter::<0>(&song)
+            .take(1000)
+            .any(|frame| frame.sum_to_mono() != 0.));
+    }
+}
@@ -5,6 +5,7 @@ use crate::audio_processing::sample::Interpolation;
 use crate::audio_processing::sample::SamplePlayer;
 use crate::audio_processing::Frame;
 use crate::manager::{OutputConfig, ToWorkerMsg};
+use crate::project::note_event::NoteAction;
 use crate::project::song::Song;
 use crate::sample::Sample;
 use dasp::sample::ToSample;
@@ -12,8 +13,14 @@ use simple_left_right::Reader;
 
 pub(crate) struct LiveAudio {
     song: Reader<Song>,
-    playback_state: Option<PlaybackState>,
+    /// done while nothing is played. Created up front and restarted for every playback,
+    /// so the audio thread doesn't allocate
+    playback_state: PlaybackState,
     live_note: Option<SamplePlayer>,
+    /// frames until the next tick of the live note. Ticks at the initial tempo of the song
+    live_note_frame: u32,
+    /// for the random waveform of the auto vibrato of the live note
+    random: u32,
     manager: rtrb::Consumer<ToWorkerMsg>,
     state_sender: triple_buffer::Input<Option<PlaybackStatus>>,
     config: OutputConfig,
@@ -34,8 +41,10 @@ impl LiveAudio {
     ) -> Self {
         Self {
             song,
-            playback_state: None,
+            playback_state: PlaybackState::stopped(config.sample_rate),
             live_note: None,
+            live_note_frame: 0,
+            random: 0x1234_5678,
             manager,
             state_sender,
             config,
@@ -46,7 +55,7 @@ impl LiveAudio {
     #[rtsan_standalone::nonblocking]
     fn send_state(&mut self) {
         self.state_sender
-            .write(self.playback_state.as_ref().map(|s| s.get_status()));
+            .write((!self.playback_state.is_done()).then(|| self.playback_state.get_status()));
     }
 
     #[rtsan_standalone::nonblocking]
@@ -60,27 +69,47 @@ impl LiveAudio {
         // process manager events
         while let Ok(event) = self.manager.pop() {
             match event {
-                ToWorkerMsg::StopPlayback => self.playback_state = None,
+                ToWorkerMsg::StopPlayback => self.playback_state.stop(),
                 ToWorkerMsg::Playback(settings) => {
-                    self.playback_state =
-                        PlaybackState::new(&song, self.config.sample_rate, settings);
-                }
-                ToWorkerMsg::PlayEvent(note) => {
-                    if let Some(sample) = &song.samples[usize::from(note.sample_instr)] {
-                        let sample_player = SamplePlayer::new(
-                            Sample::clone(&sample.1),
-                            sample.0,
-                            // this at some point was divided by two, if i ever figure out why, maybe put it back
-                            self.config.sample_rate,
-                            note.note,
-                        );
-                        self.live_note = Some(sample_player);
+                    if !self.playback_state.restart(&song, settings) {
+                        self.playback_state.stop();
                     }
                 }
+                ToWorkerMsg::PlayEvent(event) => match event.note {
+                    NoteAction::Play(note) => {
+                        if let Some((note, sample)) = song.note_sample(event.sample_instr, note) {
+                            let mut sample_player = SamplePlayer::new(
+                                Sample::clone(&sample.1),
+                                sample.0,
+                                // this at some point was divided by two, if i ever figure out why, maybe put it back
+                                self.config.sample_rate,
+                                note,
+                            );
+                            // the envelopes start on the first tick of the live note
+                            if let Some(instrument) = song.instrument(Some(event.sample_instr)) {
+                                sample_player.set_instrument(event.sample_instr, instrument);
+                            }
+                            self.live_note = Some(sample_player);
+                            self.live_note_frame = 0;
+                        }
+                    }
+                    NoteAction::Off => {
+                        if let Some(live_note) = &mut self.live_note {
+                            live_note.note_off();
+                        }
+                    }
+                    NoteAction::Fade => {
+                        if let Some(live_note) = &mut self.live_note {
+                            live_note.note_fade();
+                        }
+                    }
+                    NoteAction::Cut => self.live_note = None,
+                    NoteAction::None => (),
+                },
                 ToWorkerMsg::StopLiveNote => self.live_note = None,
             }
         }
-        if self.live_note.is_none() && self.playback_state.is_none() {
+        if self.live_note.is_none() && self.playback_state.is_done() {
             // no processing todo
             return false;
         }
@@ -91,11 +120,19 @@ impl LiveAudio {
 
         // process live_note
         if let Some(live_note) = &mut self.live_note {
-            let note_iter = live_note.iter::<{ INTERPOLATION }>();
-            buffer
-                .iter_mut()
-                .zip(note_iter)
-                .for_each(|(buf, note)| buf.add_assign(note));
+            // ticks like a voice of the playback, so note off and fade behave the same
+            for buf in buffer.iter_mut() {
+                if self.live_note_frame == 0 {
+                    live_note.tick(song.instrument(live_note.instrument()), &mut self.random);
+                    self.live_note_frame =
+                        PlaybackState::frames_per_tick(self.config.sample_rate, song.initial_tempo);
+                }
+                self.live_note_frame -= 1;
+                let Some(frame) = live_note.next::<{ INTERPOLATION }>() else {
+                    break;
+                };
+                buf.add_assign(frame * live_note.volume_factor());
+            }
 
             if live_note.check_position().is_break() {
                 self.live_note = None;
@@ -103,17 +140,11 @@ impl LiveAudio {
         }
 
         // process song playback
-        if let Some(playback) = &mut self.playback_state {
-            let playback_iter = playback.iter::<{ INTERPOLATION }>(&song);
-            buffer
-                .iter_mut()
-                .zip(playback_iter)
-                .for_each(|(buf, frame)| buf.add_assign(frame));
-
-            if playback.is_done() {
-                self.playback_state = None;
-            }
-        }
+        let playback_iter = self.playback_state.iter::<{ INTERPOLATION }>(&song);
+        buffer
+            .iter_mut()
+            .zip(playback_iter)
+            .for_each(|(buf, frame)| buf.add_assign(frame));
 
         true
     }
@@ -0,0 +1,84 @@
+use std::array;
+
+use super::note_event::Note;
+use crate::file::impulse_format::instrument::{
+    DuplicateCheckAction, DuplicateCheckType, NewNoteAction,
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EnvelopeNode {
+    pub tick: u16,
+    /// volume ranges 0..=64, pan and pitch range -32..=32
+    pub value: i8,
+}
+
+/// indices of the first and last node of the loop
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EnvelopeLoop {
+    pub start: u8,
+    pub end: u8,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Envelope {
+    pub enabled: bool,
+    /// sorted by tick
+    pub nodes: Vec<EnvelopeNode>,
+    pub envelope_loop: Option<EnvelopeLoop>,
+    /// used until the note is released
+    pub sustain_loop: Option<EnvelopeLoop>,
+    /// a new note continues the envelope of the previous one instead of restarting it
+    pub carry: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct Instrument {
+    /// for every note the note that is played instead and the sample that plays it.
+    /// Sample 0 means that the note doesn't play anything
+    pub note_sample_table: [(Note, u8); 120],
+    /// ranges 0..=128
+    pub global_volume: u8,
+    /// ranges 0..=64. None uses the pan of the sample or channel
+    pub default_pan: Option<u8>,
+    pub new_note_action: NewNoteAction,
+    pub duplicate_check_type: DuplicateCheckType,
+    pub duplicate_check_action: DuplicateCheckAction,
+    /// subtracted from the fade volume every tick once the note fades. 0 cuts the note
+    pub fadeout: u16,
+    /// maximum random change of the volume in percent
+    pub random_volume: u8,
+    /// maximum random change of the pan in percent
+    pub random_pan: u8,
+    pub volume_envelope: Envelope,
+    pub pan_envelope: Envelope,
+    pub pitch_envelope: Envelope,
+    /// the pitch envelope changes the filter cutoff instead of the pitch
+    pub filter_envelope: bool,
+    /// ranges 0..=127. None leaves the filter open
+    pub filter_cutoff: Option<u8>,
+    /// ranges 0..=127
+    pub filter_resonance: Option<u8>,
+}
+
+impl Default for Instrument {
+    fn default() -> Self {
+        Self {
+            // every note plays itself
+            note_sample_table: array::from_fn(|note| (Note::new(note as u8).unwrap(), 0)),
+            global_volume: 128,
+            default_pan: None,
+            new_note_action: NewNoteAction::default(),
+            duplicate_check_type: DuplicateCheckType::default(),
+            duplicate_check_action: DuplicateCheckAction::default(),
+            fadeout: 0,
+            random_volume: 0,
+            random_pan: 0,
+            volume_envelope: Envelope::default(),
+            pan_envelope: Envelope::default(),
+            pitch_envelope: Envelope::default(),
+            filter_envelope: false,
+            filter_cutoff: None,
+            filter_resonance: None,
+        }
+    }
+}
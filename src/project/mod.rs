@@ -1,6 +1,7 @@
 use song::Song;
 
 pub mod event_command;
+pub mod instrument;
 pub mod note_event;
 pub mod pattern;
 pub mod song;
@@ -64,9 +64,38 @@ impl Default for Note {
     }
 }
 
+/// the note column of an event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NoteAction {
+    /// the playing note isn't changed
+    #[default]
+    None,
+    Play(Note),
+    /// releases the playing note
+    Off,
+    /// stops the playing note
+    Cut,
+    /// fades out the playing note
+    Fade,
+}
+
+impl TryFrom<u8> for NoteAction {
+    type Error = u8;
+
+    /// IT Tracker Format Conversion
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            255 => Ok(Self::Off),
+            254 => Ok(Self::Cut),
+            246..=253 => Ok(Self::Fade),
+            _ => Note::new(value).map(Self::Play),
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, Default)]
 pub struct NoteEvent {
-    pub note: Note,
+    pub note: NoteAction,
     pub sample_instr: u8,
     pub vol: VolumeEffect,
     pub command: NoteCommand,
@@ -121,3 +150,23 @@ impl TryFrom<u8> for VolumeEffect {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{Note, NoteAction};
+
+    #[test]
+    fn note_action_from_it() {
+        assert_eq!(NoteAction::try_from(0), Ok(NoteAction::Play(Note(0))));
+        assert_eq!(NoteAction::try_from(199), Ok(NoteAction::Play(Note(199))));
+        assert_eq!(NoteAction::try_from(255), Ok(NoteAction::Off));
+        assert_eq!(NoteAction::try_from(254), Ok(NoteAction::Cut));
+        for value in 246..=253 {
+            assert_eq!(NoteAction::try_from(value), Ok(NoteAction::Fade));
+        }
+        // the pattern parser reports these as out of bounds
+        for value in 200..=245 {
+            assert_eq!(NoteAction::try_from(value), Err(value));
+        }
+    }
+}
@@ -2,6 +2,8 @@ use std::array;
 use std::fmt::{Debug, Formatter};
 use std::num::NonZero;
 
+use super::instrument::Instrument;
+use super::note_event::Note;
 use super::pattern::{Pattern, PatternOperation};
 use crate::channel::Pan;
 use crate::file::impulse_format;
@@ -19,12 +21,22 @@ pub struct Song {
     pub initial_tempo: NonZero<u8>,
     pub pan_separation: u8,
     pub pitch_wheel_depth: u8,
+    /// Pitch slides change the pitch linearly. Otherwise they change the period like amiga trackers.
+    pub linear_slides: bool,
+    /// IT "Old Effects". Vibrato is deeper and isn't updated on the first tick of a row.
+    pub old_effects: bool,
+    /// IT "Compatible Gxx". Gxx gets its own memory instead of sharing it with Exx and Fxx.
+    /// A sample on a Gxx row only resets the volume instead of changing the sample of the playing voice
+    pub compatible_gxx: bool,
+    /// IT "Use instruments". The sample of a note event is an instrument, which chooses the sample for each note
+    pub instrument_mode: bool,
 
     pub patterns: [Pattern; Song::MAX_PATTERNS],
     pub pattern_order: [PatternOrder; Song::MAX_ORDERS],
     pub volume: [u8; Song::MAX_CHANNELS],
     pub pan: [Pan; Song::MAX_CHANNELS],
     pub samples: [Option<(SampleMetaData, Sample)>; Song::MAX_SAMPLES_INSTR],
+    pub instruments: [Option<Instrument>; Song::MAX_SAMPLES_INSTR],
 }
 
 impl Song {
@@ -66,6 +78,10 @@ impl Song {
         self.mix_volume = header.mix_volume;
         self.pan_separation = header.pan_separation;
         self.pitch_wheel_depth = header.pitch_wheel_depth;
+        self.linear_slides = header.linear_slides();
+        self.old_effects = header.old_effects();
+        self.compatible_gxx = header.compatible_gxx();
+        self.instrument_mode = header.uses_instruments();
 
         self.pan = header.channel_pan;
         self.volume = header.channel_volume;
@@ -75,6 +91,35 @@ impl Song {
         }
     }
 
+    /// The sample that plays the note of an event and the note it plays.
+    /// In instrument mode they are looked up in the note sample table of the instrument.
+    /// Sample 0 doesn't play anything
+    pub fn note_sample(
+        &self,
+        sample_instr: u8,
+        note: Note,
+    ) -> Option<(Note, &(SampleMetaData, Sample))> {
+        let (note, sample) = if self.instrument_mode {
+            let instrument = self.instrument(Some(sample_instr))?;
+            *instrument.note_sample_table.get(usize::from(note.get()))?
+        } else {
+            (note, sample_instr)
+        };
+        if sample == 0 {
+            return None;
+        }
+        let sample = self.samples.get(usize::from(sample))?.as_ref()?;
+        Some((note, sample))
+    }
+
+    /// None in sample mode or if the instrument doesn't exist
+    pub fn instrument(&self, instrument: Option<u8>) -> Option<&Instrument> {
+        if !self.instrument_mode {
+            return None;
+        }
+        self.instruments.get(usize::from(instrument?))?.as_ref()
+    }
+
     /// debug like impl which isn't as long by cutting down a lot of information
     pub fn dbg_relevant(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
         write!(f, "global_volume: {}, ", self.global_volume)?;
@@ -83,6 +128,10 @@ impl Song {
         write!(f, "initial_tempo: {}, ", self.initial_tempo)?;
         write!(f, "pan_seperation: {}, ", self.pan_separation)?;
         write!(f, "pitch_wheel_depth: {}, ", self.pitch_wheel_depth)?;
+        write!(f, "linear_slides: {}, ", self.linear_slides)?;
+        write!(f, "old_effects: {}, ", self.old_effects)?;
+        write!(f, "compatible_gxx: {}, ", self.compatible_gxx)?;
+        write!(f, "instrument_mode: {}, ", self.instrument_mode)?;
         write!(
             f,
             "{} not empty patterns, ",
@@ -104,16 +153,22 @@ impl Default for Song {
     fn default() -> Self {
         Self {
             global_volume: 128,
-            mix_volume: Default::default(),
+            // same default as IT
+            mix_volume: 48,
             initial_speed: NonZero::new(6).unwrap(),
             initial_tempo: NonZero::new(125).unwrap(),
             pan_separation: 128,
             pitch_wheel_depth: Default::default(),
+            linear_slides: true,
+            old_effects: false,
+            compatible_gxx: false,
+            instrument_mode: false,
             patterns: array::from_fn(|_| Pattern::default()),
             pattern_order: array::from_fn(|_| PatternOrder::default()),
             volume: array::from_fn(|_| 64),
             pan: array::from_fn(|_| Pan::default()),
             samples: array::from_fn(|_| None),
+            instruments: array::from_fn(|_| None),
         }
     }
 }
@@ -125,11 +180,14 @@ pub enum SongOperation {
     SetPan(u8, Pan),
     SetSample(u8, SampleMetaData, Sample),
     RemoveSample(u8),
+    SetInstrument(u8, Box<Instrument>),
+    RemoveInstrument(u8),
     PatternOperation(u8, PatternOperation),
     SetOrder(u16, PatternOrder),
     SetInitialSpeed(NonZero<u8>),
     SetInitialTempo(NonZero<u8>),
     SetGlobalVol(u8),
+    SetInstrumentMode(bool),
 }
 
 /// keep in sync with SongOperation
@@ -139,11 +197,14 @@ pub(crate) enum ValidOperation {
     SetPan(u8, Pan),
     SetSample(u8, SampleMetaData, Sample),
     RemoveSample(u8),
+    SetInstrument(u8, Box<Instrument>),
+    RemoveInstrument(u8),
     PatternOperation(u8, PatternOperation),
     SetOrder(u16, PatternOrder),
     SetInitialSpeed(NonZero<u8>),
     SetInitialTempo(NonZero<u8>),
     SetGlobalVol(u8),
+    SetInstrumentMode(bool),
 }
 
 impl ValidOperation {
@@ -157,6 +218,8 @@ impl ValidOperation {
             SongOperation::SetPan(c, _) => usize::from(c) < Song::MAX_CHANNELS,
             SongOperation::SetSample(idx, _, _) => usize::from(idx) < Song::MAX_SAMPLES_INSTR,
             SongOperation::RemoveSample(idx) => usize::from(idx) < Song::MAX_SAMPLES_INSTR,
+            SongOperation::SetInstrument(idx, _) => usize::from(idx) < Song::MAX_SAMPLES_INSTR,
+            SongOperation::RemoveInstrument(idx) => usize::from(idx) < Song::MAX_SAMPLES_INSTR,
             SongOperation::PatternOperation(idx, op) => match song.patterns.get(usize::from(idx)) {
                 Some(pattern) => pattern.operation_is_valid(&op),
                 None => false,
@@ -165,6 +228,7 @@ impl ValidOperation {
             SongOperation::SetInitialSpeed(_) => true,
             SongOperation::SetInitialTempo(_) => true,
             SongOperation::SetGlobalVol(_) => true,
+            SongOperation::SetInstrumentMode(_) => true,
         };
 
         if valid {
@@ -176,6 +240,8 @@ impl ValidOperation {
                     Self::SetSample(i, meta_data, sample)
                 }
                 SongOperation::RemoveSample(i) => Self::RemoveSample(i),
+                SongOperation::SetInstrument(i, instrument) => Self::SetInstrument(i, instrument),
+                SongOperation::RemoveInstrument(i) => Self::RemoveInstrument(i),
                 SongOperation::PatternOperation(i, pattern_operation) => {
                     Self::PatternOperation(i, pattern_operation)
                 }
@@ -183,6 +249,7 @@ impl ValidOperation {
                 SongOperation::SetInitialSpeed(s) => Self::SetInitialSpeed(s),
                 SongOperation::SetInitialTempo(t) => Self::SetInitialTempo(t),
                 SongOperation::SetGlobalVol(v) => Self::SetGlobalVol(v),
+                SongOperation::SetInstrumentMode(m) => Self::SetInstrumentMode(m),
             })
         } else {
             Err(op)
@@ -199,6 +266,10 @@ impl simple_left_right::Absorb<ValidOperation> for Song {
                 self.samples[usize::from(i)] = Some((meta, sample))
             }
             ValidOperation::RemoveSample(i) => self.samples[usize::from(i)] = None,
+            ValidOperation::SetInstrument(i, instrument) => {
+                self.instruments[usize::from(i)] = Some(*instrument)
+            }
+            ValidOperation::RemoveInstrument(i) => self.instruments[usize::from(i)] = None,
             ValidOperation::PatternOperation(i, op) => {
                 self.patterns[usize::from(i)].apply_operation(op)
             }
@@ -206,6 +277,7 @@ impl simple_left_right::Absorb<ValidOperation> for Song {
             ValidOperation::SetInitialSpeed(s) => self.initial_speed = s,
             ValidOperation::SetInitialTempo(t) => self.initial_tempo = t,
             ValidOperation::SetGlobalVol(v) => self.global_volume = v,
+            ValidOperation::SetInstrumentMode(m) => self.instrument_mode = m,
         }
     }
 }
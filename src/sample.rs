@@ -45,6 +45,20 @@ impl Sample {
         self.mono
     }
 
+    /// both share the same sample data
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.data, &other.data)
+    }
+
+    /// len in Frames, without the padding
+    pub fn len(&self) -> usize {
+        self.len_with_pad() - 2 * Self::PAD_SIZE_EACH
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     /// len in Frames
     pub fn len_with_pad(&self) -> usize {
         if self.mono {
@@ -128,6 +142,29 @@ impl Debug for Sample {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoopMode {
+    Forward,
+    PingPong,
+}
+
+/// in frames, without the padding
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SampleLoop {
+    pub start: usize,
+    /// first frame after the loop
+    pub end: usize,
+    pub mode: LoopMode,
+}
+
+impl SampleLoop {
+    /// clamps the end to the sample length. None if the loop would be empty
+    pub fn clamped(self, len: usize) -> Option<Self> {
+        let end = self.end.min(len);
+        (self.start < end).then_some(Self { end, ..self })
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct SampleMetaData {
     pub default_volume: u8,
@@ -139,4 +176,7 @@ pub struct SampleMetaData {
     pub vibrato_waveform: VibratoWave,
     pub sample_rate: NonZero<u32>,
     pub base_note: Note,
+    pub sample_loop: Option<SampleLoop>,
+    /// played instead of the sample loop until the note is released
+    pub sustain_loop: Option<SampleLoop>,
 }